@@ -0,0 +1,340 @@
+use crate::language;
+use rust_stemmers::Stemmer;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use unicode_normalization::UnicodeNormalization;
+
+// common english words that say nothing about what a page is about
+const ENGLISH_STOP_WORDS: &str = "\
+a about above after again against all also am an and any are as at be because been before \
+being below between both but by can could did do does doing down during each few for from \
+further had has have having he her here hers herself him himself his how i if in into is it \
+its itself just me more most my myself no nor not now of off on once only or other our ours \
+ourselves out over own same she should so some such than that the their theirs them themselves \
+then there these they this those through to too under until up very was we were what when \
+where which while who whom why will with would you your yours yourself yourselves";
+
+/**
+ * A term along with its position (counted in words) in the text it came from
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub term: String,
+    pub position: u32,
+}
+
+/**
+ * Turns text into the terms that get indexed. The same analyzer is used for page content and
+ * for search queries so both end up with the same terms.
+ *
+ * Text is unicode normalized (NFKC) and lowercased, split on anything that isn't a letter or a
+ * digit, and terms that are stop words or outside the length limits (counted in characters) are
+ * dropped. Scripts written without spaces are split into bigrams instead. The remaining tokens
+ * are stemmed for the language of the document when we know it.
+ */
+pub struct Analyzer {
+    min_length: usize,
+    max_length: usize,
+    stop_words: HashSet<String>,
+    // language code -> stemmer
+    stemmers: HashMap<&'static str, Stemmer>,
+}
+
+impl Analyzer {
+    /**
+     * Creates an analyzer with the built in english stop words
+     */
+    pub fn new(min_length: usize, max_length: usize) -> Self {
+        Analyzer {
+            min_length,
+            max_length,
+            stop_words: ENGLISH_STOP_WORDS
+                .split_whitespace()
+                .map(|w| w.to_string())
+                .collect(),
+            stemmers: language::stemmed_languages()
+                .filter_map(|code| Some((code, language::stemmer(code)?)))
+                .collect(),
+        }
+    }
+
+    /**
+     * Replaces the stop words with the words in a file, one per line (or whitespace separated)
+     */
+    pub fn load_stop_words(&mut self, path: &Path) -> io::Result<()> {
+        let contents = fs::read_to_string(path)?;
+        self.stop_words = contents.split_whitespace().map(normalize).collect();
+        Ok(())
+    }
+
+    /**
+     * The terms a document is indexed under: its tokens stemmed for the document's language, or
+     * left as they are if the language is unknown or can't be stemmed
+     */
+    pub fn terms(&self, text: &str, lang: Option<&str>) -> Vec<Token> {
+        let mut tokens = self.analyze(text);
+        if let Some(stemmer) = self.stemmer(lang) {
+            for token in tokens.iter_mut() {
+                token.term = stemmer.stem(&token.term).into_owned();
+            }
+        }
+        tokens
+    }
+
+    /**
+     * The stemmer for the language, None if the language is unknown or can't be stemmed
+     */
+    pub fn stemmer(&self, lang: Option<&str>) -> Option<&Stemmer> {
+        lang.and_then(|lang| self.stemmers.get(lang))
+    }
+
+    /**
+     * The terms a query token could have been indexed under. With a language that is its stem in
+     * that language, otherwise the token as is (for documents of unknown language) plus its stem
+     * in every language we can stem.
+     */
+    pub fn query_variants(&self, token: &str, lang: Option<&str>) -> Vec<String> {
+        let mut variants = Vec::new();
+        match lang {
+            Some(lang) => match self.stemmers.get(lang) {
+                Some(stemmer) => variants.push(stemmer.stem(token).into_owned()),
+                None => variants.push(token.to_string()),
+            },
+            None => {
+                variants.push(token.to_string());
+                for stemmer in self.stemmers.values() {
+                    let stem = stemmer.stem(token).into_owned();
+                    if !variants.contains(&stem) {
+                        variants.push(stem);
+                    }
+                }
+            }
+        }
+        variants
+    }
+
+    pub fn tokens(&self, text: &str) -> Vec<String> {
+        self.analyze(text)
+            .into_iter()
+            .map(|token| token.term)
+            .collect()
+    }
+
+    /**
+     * Splits the text into tokens along with their positions. Dropped words (stop words, too
+     * short or too long) still take up a position so that phrases only match the same words in
+     * the same places.
+     */
+    pub fn analyze(&self, text: &str) -> Vec<Token> {
+        let normalized = normalize(text);
+        let mut tokens = Vec::new();
+        let mut position = 0;
+        let mut token = String::new();
+        // run of characters from a script written without spaces between words
+        let mut unspaced: Vec<char> = Vec::new();
+        let mut chars = normalized.chars().peekable();
+        while let Some(c) = chars.next() {
+            if is_unspaced(c) {
+                if !token.is_empty() {
+                    self.push_token(&mut tokens, std::mem::take(&mut token), &mut position);
+                }
+                unspaced.push(c);
+                continue;
+            }
+            if !unspaced.is_empty() {
+                push_bigrams(&mut tokens, &std::mem::take(&mut unspaced), &mut position);
+            }
+
+            if c.is_alphanumeric() {
+                token.push(c);
+            } else if is_apostrophe(c)
+                && !token.is_empty()
+                && chars.peek().is_some_and(|next| next.is_alphanumeric())
+            {
+                // keep contractions and possessives together: "don't" -> "dont"
+            } else if !token.is_empty() {
+                self.push_token(&mut tokens, std::mem::take(&mut token), &mut position);
+            }
+        }
+        if !token.is_empty() {
+            self.push_token(&mut tokens, token, &mut position);
+        }
+        if !unspaced.is_empty() {
+            push_bigrams(&mut tokens, &unspaced, &mut position);
+        }
+        tokens
+    }
+
+    fn push_token(&self, tokens: &mut Vec<Token>, term: String, position: &mut u32) {
+        let length = term.chars().count();
+        *position += 1;
+        if length < self.min_length || length > self.max_length {
+            return;
+        }
+        if self.stop_words.contains(&term) {
+            return;
+        }
+        tokens.push(Token {
+            term,
+            position: *position - 1,
+        });
+    }
+}
+
+/**
+ * Unicode normalizes (NFKC) and lowercases text, the first step of the analysis
+ */
+pub fn normalize(text: &str) -> String {
+    text.nfkc().collect::<String>().to_lowercase()
+}
+
+fn is_apostrophe(c: char) -> bool {
+    c == '\'' || c == '\u{2019}'
+}
+
+/**
+ * Chinese, Japanese, Korean, Thai, Lao, Khmer and Myanmar text doesn't (reliably) separate words
+ * with spaces, so a whole sentence would otherwise end up as a single token.
+ */
+pub fn is_unspaced(c: char) -> bool {
+    matches!(c as u32,
+        0x0E01..=0x0E3A | 0x0E40..=0x0E4E // thai letters, vowels and tone marks
+        | 0x0E81..=0x0EDF // lao
+        | 0x1000..=0x103F // myanmar
+        | 0x1780..=0x17D3 // khmer
+        | 0x3041..=0x309F // hiragana
+        | 0x30A1..=0x30FA | 0x30FC..=0x30FF | 0x31F0..=0x31FF // katakana
+        | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF | 0x20000..=0x2FFFF // han
+        | 0x1100..=0x11FF | 0x3131..=0x318E | 0xAC00..=0xD7A3 // hangul
+    )
+}
+
+/**
+ * Splits a run of unspaced text into overlapping two character terms ("東京都" becomes "東京",
+ * "京都"). A query goes through the same split, so searching for a word finds the pages that
+ * contain all of its bigrams without needing a dictionary of the language. The length limits
+ * and stop words don't apply to these.
+ */
+fn push_bigrams(tokens: &mut Vec<Token>, run: &[char], position: &mut u32) {
+    if run.len() == 1 {
+        tokens.push(Token {
+            term: run[0].to_string(),
+            position: *position,
+        });
+        *position += 1;
+        return;
+    }
+    for pair in run.windows(2) {
+        tokens.push(Token {
+            term: pair.iter().collect(),
+            position: *position,
+        });
+        *position += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_punctuation() {
+        let analyzer = Analyzer::new(2, 64);
+        assert_eq!(
+            analyzer.tokens("Energy, energy. (ENERGY!) \"energy\""),
+            vec!["energy", "energy", "energy", "energy"]
+        );
+        assert_eq!(
+            analyzer.tokens("peer-to-peer IPFS/libp2p"),
+            vec!["peer", "peer", "ipfs", "libp2p"]
+        );
+    }
+
+    #[test]
+    fn removes_stop_words_and_keeps_contractions() {
+        let analyzer = Analyzer::new(2, 64);
+        assert_eq!(
+            analyzer.tokens("The cat's toy isn't in the box"),
+            vec!["cats", "toy", "isnt", "box"]
+        );
+    }
+
+    #[test]
+    fn length_is_counted_in_characters() {
+        let analyzer = Analyzer::new(4, 5);
+        assert_eq!(
+            analyzer.tokens("ipfs über éclair naïve cat elephant"),
+            vec!["ipfs", "über", "naïve"]
+        );
+    }
+
+    #[test]
+    fn stems_for_the_document_language() {
+        let analyzer = Analyzer::new(2, 64);
+        let terms = |text, lang| -> Vec<String> {
+            analyzer
+                .terms(text, lang)
+                .into_iter()
+                .map(|token| token.term)
+                .collect()
+        };
+        assert_eq!(terms("running runs", Some("eng")), vec!["run", "run"]);
+        assert_eq!(terms("running runs", None), vec!["running", "runs"]);
+        assert_eq!(analyzer.query_variants("running", Some("eng")), vec!["run"]);
+        let variants = analyzer.query_variants("running", None);
+        assert_eq!(variants[0], "running");
+        assert!(variants.contains(&"run".to_string()));
+    }
+
+    #[test]
+    fn positions_count_dropped_words() {
+        let analyzer = Analyzer::new(2, 64);
+        let positions: Vec<(String, u32)> = analyzer
+            .analyze("The cat in the hat, 東京都")
+            .into_iter()
+            .map(|token| (token.term, token.position))
+            .collect();
+        assert_eq!(
+            positions,
+            vec![
+                ("cat".to_string(), 1),
+                ("hat".to_string(), 4),
+                ("東京".to_string(), 5),
+                ("京都".to_string(), 6)
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_unspaced_scripts_into_bigrams() {
+        let analyzer = Analyzer::new(2, 64);
+        assert_eq!(
+            analyzer.tokens("星际文件系统IPFS。你好"),
+            vec!["星际", "际文", "文件", "件系", "系统", "ipfs", "你好"]
+        );
+        // katakana and kanji, with the punctuation in between splitting the runs
+        assert_eq!(
+            analyzer.tokens("インター・ネット 東京"),
+            vec!["イン", "ンタ", "ター", "ネッ", "ット", "東京"]
+        );
+        // the tone mark (่) stays part of the word
+        assert_eq!(
+            analyzer.tokens("ภาษาไทย"),
+            vec!["ภา", "าษ", "ษา", "าไ", "ไท", "ทย"]
+        );
+        assert_eq!(analyzer.tokens("ไม่"), vec!["ไม", "ม่"]);
+        assert_eq!(analyzer.tokens("猫"), vec!["猫"]);
+    }
+
+    #[test]
+    fn normalizes_unicode() {
+        let analyzer = Analyzer::new(2, 64);
+        // fullwidth letters and a decomposed e + combining accent
+        assert_eq!(
+            analyzer.tokens("ＩＰＦＳ cafe\u{301}"),
+            vec!["ipfs", "caf\u{e9}"]
+        );
+    }
+}
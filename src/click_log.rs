@@ -0,0 +1,198 @@
+use crate::index_queue::IndexQueue;
+use crate::index_result::now;
+use crate::index_suggest::normalize_query;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+// seconds after which a click counts half as much
+const HALF_LIFE: f64 = 7.0 * 24.0 * 60.0 * 60.0;
+// clicks remembered per item and per query and item, the counts are halved when there are more
+const MAX_ENTRIES: usize = 100_000;
+// a client clicking the same result for the same query again within this many seconds is not
+// counted again
+const REPEAT_WINDOW: u64 = 24 * 60 * 60;
+// a count is forgotten once it decays below this
+const MIN_CLICKS: f64 = 0.5;
+// how much clicks on an item for any query, and for the query being searched, raise its score
+const ITEM_WEIGHT: f64 = 0.2;
+const QUERY_WEIGHT: f64 = 0.5;
+
+#[derive(Deserialize)]
+pub struct ClickParams {
+    // the query the result was found with
+    pub q: Option<String>,
+    pub cid: String,
+}
+
+/**
+ * A click count that decays over time, stored as its value at the time it was last updated
+ */
+#[derive(Clone, Copy)]
+struct Decaying {
+    value: f64,
+    at: u64,
+}
+
+impl Decaying {
+    fn value_at(&self, time: u64) -> f64 {
+        let elapsed = time.saturating_sub(self.at) as f64;
+        self.value * 0.5f64.powf(elapsed / HALF_LIFE)
+    }
+
+    fn add(&mut self, time: u64) {
+        self.value = self.value_at(time) + 1.0;
+        self.at = self.at.max(time);
+    }
+}
+
+#[derive(Default)]
+struct Clicks {
+    // item -> clicks on it from any search
+    items: HashMap<String, Decaying>,
+    // (normalized query, item) -> clicks on the item from that search
+    queries: HashMap<(String, String), Decaying>,
+    // (client, normalized query, item) -> when a click on it last counted
+    counted: HashMap<(String, String, String), u64>,
+}
+
+/**
+ * The search results people clicked on, so the ones clicked most recently and most often can be
+ * ranked higher. Old clicks count for less and less, so rankings follow what is popular now.
+ */
+#[derive(Default)]
+pub struct ClickLog {
+    clicks: RwLock<Clicks>,
+}
+
+impl ClickLog {
+    pub fn new() -> Self {
+        ClickLog::default()
+    }
+
+    /**
+     * Counts a click by the client, unless it already clicked the same result for the same query
+     * recently. Returns whether the click counted.
+     */
+    pub fn record(&self, client: &str, query: &str, item: &str) -> bool {
+        self.record_at(client, query, item, now())
+    }
+
+    fn record_at(&self, client: &str, query: &str, item: &str, time: u64) -> bool {
+        let query = normalize_query(query);
+        let mut clicks = self.clicks.write().unwrap();
+        let recent = |counted: &u64| time.saturating_sub(*counted) < REPEAT_WINDOW;
+        let key = (client.to_string(), query.clone(), item.to_string());
+        if clicks.counted.get(&key).is_some_and(recent) {
+            return false;
+        }
+        if clicks.counted.len() >= MAX_ENTRIES {
+            clicks.counted.retain(|_, counted| recent(counted));
+            // more clients clicking than can be told apart, stop counting until some expire
+            if clicks.counted.len() >= MAX_ENTRIES {
+                return false;
+            }
+        }
+        clicks.counted.insert(key, time);
+        add(&mut clicks.items, item.to_string(), time);
+        if !query.is_empty() {
+            add(&mut clicks.queries, (query, item.to_string()), time);
+        }
+        true
+    }
+
+    /**
+     * What a search score for the item is multiplied by: 1 for items nobody clicked on, growing
+     * with the log of the clicks, and more so for clicks from the same search
+     */
+    pub fn popularity(&self, query: &str, item: &str) -> f64 {
+        self.popularity_at(&normalize_query(query), item, now())
+    }
+
+    fn popularity_at(&self, query: &str, item: &str, time: u64) -> f64 {
+        let clicks = self.clicks.read().unwrap();
+        let item_clicks = clicks.items.get(item).map_or(0.0, |c| c.value_at(time));
+        let query_clicks = clicks
+            .queries
+            .get(&(query.to_string(), item.to_string()))
+            .map_or(0.0, |c| c.value_at(time));
+        1.0 + ITEM_WEIGHT * item_clicks.ln_1p() + QUERY_WEIGHT * query_clicks.ln_1p()
+    }
+}
+
+fn add<K: Eq + std::hash::Hash>(counts: &mut HashMap<K, Decaying>, key: K, time: u64) {
+    if counts.len() >= MAX_ENTRIES && !counts.contains_key(&key) {
+        // make room by aging every count, rarely clicked entries drop out
+        counts.retain(|_, count| {
+            count.value = count.value_at(time) / 2.0;
+            count.at = time;
+            count.value >= MIN_CLICKS
+        });
+    }
+    counts
+        .entry(key)
+        .or_insert(Decaying {
+            value: 0.0,
+            at: time,
+        })
+        .add(time);
+}
+
+impl IndexQueue {
+    /**
+     * Records a click by the client on a search result and returns the public gateway url to send
+     * the user to. Only clicks on indexed items count.
+     */
+    pub fn click(&self, client: &str, params: &ClickParams) -> String {
+        if self.map.contains_key(&params.cid) {
+            self.clicks
+                .record(client, params.q.as_deref().unwrap_or(""), &params.cid);
+        }
+        format!("{}/ipfs/{}", self.public_gateway, params.cid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clicks_raise_popularity() {
+        let log = ClickLog::new();
+        log.record_at("x", "Solar Power", "a", 1000);
+        log.record_at("y", "solar power", "a", 1000);
+        log.record_at("x", "wind", "b", 1000);
+        assert_eq!(log.popularity_at("solar power", "c", 1000), 1.0);
+        let a = log.popularity_at("solar power", "a", 1000);
+        assert!(a > log.popularity_at("wind", "a", 1000));
+        assert!(a > log.popularity_at("wind", "b", 1000));
+        let b = log.popularity_at("wind", "b", 1000);
+        assert!(b > log.popularity_at("solar power", "b", 1000));
+    }
+
+    #[test]
+    fn clicks_decay() {
+        let log = ClickLog::new();
+        log.record_at("x", "", "a", 0);
+        let clicks = |time: u64| log.clicks.read().unwrap().items["a"].value_at(time);
+        assert_eq!(clicks(0), 1.0);
+        assert!((clicks(HALF_LIFE as u64) - 0.5).abs() < 1e-9);
+        log.record_at("x", "", "a", HALF_LIFE as u64);
+        assert!((clicks(HALF_LIFE as u64) - 1.5).abs() < 1e-9);
+        let later = 10 * HALF_LIFE as u64;
+        assert!(log.popularity_at("", "a", 0) > log.popularity_at("", "a", later));
+    }
+
+    #[test]
+    fn repeated_clicks_count_once() {
+        let log = ClickLog::new();
+        assert!(log.record_at("x", "solar", "a", 0));
+        assert!(!log.record_at("x", "Solar", "a", 10));
+        let once = log.popularity_at("solar", "a", 10);
+        assert!(log.record_at("y", "solar", "a", 10));
+        assert!(log.popularity_at("solar", "a", 10) > once);
+        // a different query, or the same one a day later, counts again
+        assert!(log.record_at("x", "solar panels", "a", 10));
+        assert!(log.record_at("x", "solar", "a", REPEAT_WINDOW));
+    }
+}
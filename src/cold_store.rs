@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+// the file is rewritten once it is mostly taken up by values that have been taken back out
+const MIN_COMPACT_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Default)]
+struct Log {
+    file: Option<File>,
+    // key -> offset and length of the value in the file
+    index: HashMap<String, (u64, u64)>,
+    // bytes written to the file, and the part of them no longer in the index
+    len: u64,
+    garbage: u64,
+}
+
+/**
+ * Values moved out of memory to a file on disk. Values are appended to the file and found through
+ * an index kept in memory, which is much smaller than the values themselves.
+ *
+ * Nothing is kept across restarts: the index only lives in memory, so the file is truncated when
+ * it is first written to.
+ */
+pub struct ColdStore {
+    path: PathBuf,
+    log: Mutex<Log>,
+}
+
+impl ColdStore {
+    pub fn new(path: PathBuf) -> Self {
+        ColdStore {
+            path,
+            log: Mutex::new(Log::default()),
+        }
+    }
+
+    pub fn put(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        let mut log = self.log.lock().unwrap();
+        if log.file.is_none() {
+            if let Some(parent) = self.path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            log.file = Some(
+                OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(&self.path)?,
+            );
+        }
+        let offset = log.len;
+        let file = log.file.as_mut().unwrap();
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(value)?;
+        log.len += value.len() as u64;
+        if let Some((_, replaced)) = log
+            .index
+            .insert(key.to_string(), (offset, value.len() as u64))
+        {
+            log.garbage += replaced;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let mut log = self.log.lock().unwrap();
+        let Some((offset, len)) = log.index.get(key).cloned() else {
+            return Ok(None);
+        };
+        let file = log.file.as_mut().unwrap();
+        let mut value = vec![0; len as usize];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut value)?;
+        Ok(Some(value))
+    }
+
+    /**
+     * Removes the value from the store and returns it
+     */
+    pub fn take(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let value = self.get(key)?;
+        if value.is_some() {
+            self.remove(key)?;
+        }
+        Ok(value)
+    }
+
+    pub fn remove(&self, key: &str) -> io::Result<()> {
+        let mut log = self.log.lock().unwrap();
+        if let Some((_, len)) = log.index.remove(key) {
+            log.garbage += len;
+            if log.garbage > MIN_COMPACT_BYTES && log.garbage * 2 > log.len {
+                self.compact(&mut log)?;
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Number of values and the bytes they take up on disk, including space not reclaimed yet
+     */
+    pub fn len(&self) -> (usize, u64) {
+        let log = self.log.lock().unwrap();
+        (log.index.len(), log.len)
+    }
+
+    /**
+     * Copies the values still in the index to a new file, dropping the ones taken out
+     */
+    fn compact(&self, log: &mut Log) -> io::Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        let mut compacted = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp)?;
+        let file = log.file.as_mut().unwrap();
+        let mut len = 0;
+        for (offset, value_len) in log.index.values_mut() {
+            let mut value = vec![0; *value_len as usize];
+            file.seek(SeekFrom::Start(*offset))?;
+            file.read_exact(&mut value)?;
+            compacted.write_all(&value)?;
+            *offset = len;
+            len += *value_len;
+        }
+        fs::rename(&tmp, &self.path)?;
+        log.file = Some(compacted);
+        log.len = len;
+        log.garbage = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn puts_and_takes() {
+        let dir = std::env::temp_dir().join(format!("ipfs_indexer_cold_{}", std::process::id()));
+        let store = ColdStore::new(dir.join("values.log"));
+        store.put("a", b"first").unwrap();
+        store.put("b", b"second").unwrap();
+        store.put("a", b"third").unwrap();
+        assert_eq!(store.get("a").unwrap().as_deref(), Some(&b"third"[..]));
+        assert_eq!(store.take("b").unwrap().as_deref(), Some(&b"second"[..]));
+        assert_eq!(store.get("b").unwrap(), None);
+        assert_eq!(store.len(), (1, 16));
+
+        let mut log = store.log.lock().unwrap();
+        store.compact(&mut log).unwrap();
+        drop(log);
+        assert_eq!(store.len(), (1, 5));
+        assert_eq!(store.get("a").unwrap().as_deref(), Some(&b"third"[..]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
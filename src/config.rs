@@ -0,0 +1,235 @@
+use crate::field::Boosts;
+use crate::gateway::Selection;
+use log::warn;
+use std::path::PathBuf;
+use std::slice::Iter;
+use std::time::Duration;
+
+pub const USAGE: &str = "Usage: ipfs_indexer [--selection round-robin|latency] [--data-dir <dir>] \
+[--seeds-file <file>] [--reseed-interval <seconds>] [--queue-capacity <items>] \
+[--min-token-length <chars>] [--max-token-length <chars>] [--stop-words <file>] \
+[--boost <field>=<weight> ...] [--pagerank-interval <seconds>] [--memory-budget <megabytes>] \
+[--public-gateway <url>] <ipfs_node_address> [<ipfs_node_address> ...]";
+
+/**
+ * Settings taken from the command line, see USAGE.
+ *
+ * Each gateway is a host[:port]; several can be given as separate arguments or comma separated.
+ */
+pub struct Config {
+    pub gateways: Vec<String>,
+    pub selection: Selection,
+    // where state that should survive a restart is kept, nothing is persisted when unset
+    pub data_dir: Option<PathBuf>,
+    // file with seed CIDs (one per line) added to the seed list at startup
+    pub seeds_file: Option<PathBuf>,
+    // how often the seeds are re-crawled, zero disables re-seeding
+    pub reseed_interval: Duration,
+    // maximum number of items waiting to be indexed
+    pub queue_capacity: usize,
+    // terms shorter or longer than this (in characters) are not indexed
+    pub min_token_length: usize,
+    pub max_token_length: usize,
+    // replaces the built in stop words, one word per line
+    pub stop_words_file: Option<PathBuf>,
+    // weight of a match in each field (body, title, heading, meta, alt, anchor) when scoring
+    pub boosts: Boosts,
+    // how often the PageRank of the link graph is computed (and the graph saved), zero disables it
+    pub pagerank_interval: Duration,
+    // bytes the index may take up before parts of it are moved to the data directory, no limit
+    // when unset
+    pub memory_budget: Option<u64>,
+    // where /click sends people, the crawl gateways may not be reachable from outside
+    pub public_gateway: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            gateways: vec!["ipfs.io".to_string()],
+            selection: Selection::RoundRobin,
+            data_dir: Some(PathBuf::from("data")),
+            seeds_file: None,
+            reseed_interval: Duration::from_secs(6 * 60 * 60),
+            queue_capacity: 100_000,
+            min_token_length: 2,
+            max_token_length: 64,
+            stop_words_file: None,
+            boosts: Boosts::default(),
+            pagerank_interval: Duration::from_secs(10 * 60),
+            memory_budget: None,
+            public_gateway: "https://ipfs.io".to_string(),
+        }
+    }
+}
+
+fn value<'a>(iter: &mut Iter<'a, String>, option: &str) -> Option<&'a str> {
+    let value = iter.next().map(|s| s.as_str());
+    if value.is_none() {
+        warn!("Missing value for {}", option);
+    }
+    value
+}
+
+impl Config {
+    /**
+     * Parses the arguments following the program name. Unknown options are logged and skipped.
+     */
+    pub fn from_args(args: &[String]) -> Config {
+        let mut config = Config::default();
+        let mut gateways = Vec::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--selection" => {
+                    let value = value(&mut iter, arg).unwrap_or("");
+                    match Selection::parse(value) {
+                        Some(selection) => config.selection = selection,
+                        None => warn!("Unknown gateway selection '{}'", value),
+                    }
+                }
+                "--data-dir" => {
+                    if let Some(dir) = value(&mut iter, arg) {
+                        config.data_dir = Some(PathBuf::from(dir));
+                    }
+                }
+                "--seeds-file" => {
+                    if let Some(file) = value(&mut iter, arg) {
+                        config.seeds_file = Some(PathBuf::from(file));
+                    }
+                }
+                "--reseed-interval" => {
+                    let value = value(&mut iter, arg).unwrap_or("");
+                    match value.parse() {
+                        Ok(seconds) => config.reseed_interval = Duration::from_secs(seconds),
+                        Err(_) => warn!("Invalid reseed interval '{}'", value),
+                    }
+                }
+                "--queue-capacity" => {
+                    let value = value(&mut iter, arg).unwrap_or("");
+                    match value.parse() {
+                        Ok(capacity) if capacity > 0 => config.queue_capacity = capacity,
+                        _ => warn!("Invalid queue capacity '{}'", value),
+                    }
+                }
+                "--min-token-length" | "--max-token-length" => {
+                    let value = value(&mut iter, arg).unwrap_or("");
+                    match value.parse() {
+                        Ok(length) if arg == "--min-token-length" => {
+                            config.min_token_length = length
+                        }
+                        Ok(length) => config.max_token_length = length,
+                        Err(_) => warn!("Invalid token length '{}'", value),
+                    }
+                }
+                "--stop-words" => {
+                    if let Some(file) = value(&mut iter, arg) {
+                        config.stop_words_file = Some(PathBuf::from(file));
+                    }
+                }
+                "--pagerank-interval" => {
+                    let value = value(&mut iter, arg).unwrap_or("");
+                    match value.parse() {
+                        Ok(seconds) => config.pagerank_interval = Duration::from_secs(seconds),
+                        Err(_) => warn!("Invalid pagerank interval '{}'", value),
+                    }
+                }
+                "--memory-budget" => {
+                    let value = value(&mut iter, arg).unwrap_or("");
+                    match value.parse::<u64>() {
+                        Ok(0) => config.memory_budget = None,
+                        Ok(megabytes) => config.memory_budget = Some(megabytes * 1024 * 1024),
+                        Err(_) => warn!("Invalid memory budget '{}'", value),
+                    }
+                }
+                "--public-gateway" => {
+                    if let Some(url) = value(&mut iter, arg) {
+                        config.public_gateway = url.to_string();
+                    }
+                }
+                "--boost" => {
+                    let value = value(&mut iter, arg).unwrap_or("");
+                    if !config.boosts.set(value) {
+                        warn!("Invalid boost '{}', expected <field>=<weight>", value);
+                    }
+                }
+                option if option.starts_with("--") => warn!("Unknown option {}", option),
+                _ => gateways.extend(
+                    arg.split(',')
+                        .filter(|g| !g.is_empty())
+                        .map(|g| g.to_string()),
+                ),
+            }
+        }
+        if !gateways.is_empty() {
+            config.gateways = gateways;
+        }
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::Field;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn defaults_to_ipfs_io() {
+        let config = Config::from_args(&[]);
+        assert_eq!(config.gateways, vec!["ipfs.io"]);
+        assert_eq!(config.selection, Selection::RoundRobin);
+    }
+
+    #[test]
+    fn seed_options() {
+        let config = Config::from_args(&args(&[
+            "--data-dir",
+            "/var/lib/indexer",
+            "--seeds-file",
+            "seeds.txt",
+            "--reseed-interval",
+            "60",
+            "--pagerank-interval",
+            "0",
+            "--memory-budget",
+            "512",
+            "--public-gateway",
+            "https://dweb.link",
+            "ipfs:8080",
+        ]));
+        assert_eq!(config.data_dir, Some(PathBuf::from("/var/lib/indexer")));
+        assert_eq!(config.seeds_file, Some(PathBuf::from("seeds.txt")));
+        assert_eq!(config.reseed_interval, Duration::from_secs(60));
+        assert!(config.pagerank_interval.is_zero());
+        assert_eq!(config.memory_budget, Some(512 * 1024 * 1024));
+        assert_eq!(config.public_gateway, "https://dweb.link");
+        assert_eq!(config.gateways, vec!["ipfs:8080"]);
+    }
+
+    #[test]
+    fn multiple_gateways() {
+        let config = Config::from_args(&args(&[
+            "--selection",
+            "latency",
+            "ipfs:8080,ipfs.io",
+            "dweb.link",
+        ]));
+        assert_eq!(config.gateways, vec!["ipfs:8080", "ipfs.io", "dweb.link"]);
+        assert_eq!(config.selection, Selection::LatencyWeighted);
+    }
+
+    #[test]
+    fn field_boosts() {
+        let config = Config::from_args(&args(&["--boost", "title=5", "--boost", "alt"]));
+        assert_eq!(config.boosts.get(Field::Title), 5.0);
+        // the invalid one is skipped
+        assert_eq!(
+            config.boosts.get(Field::Alt),
+            Boosts::default().get(Field::Alt)
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use scraper::{ElementRef, Html, Node, Selector};
+
+// elements whose contents are never shown as text
+const HIDDEN_TAGS: [&str; 9] = [
+    "script", "style", "noscript", "template", "svg", "canvas", "iframe", "object", "head",
+];
+// elements that hold the same links and text on every page of a site
+const BOILERPLATE_TAGS: [&str; 4] = ["nav", "header", "footer", "aside"];
+const BOILERPLATE_ROLES: [&str; 5] = [
+    "navigation",
+    "banner",
+    "contentinfo",
+    "complementary",
+    "search",
+];
+// class names and ids of boilerplate blocks. Only whole names count, with _ read as -, words
+// in them say too little: a body with class="has-sidebar" is the whole page
+const BOILERPLATE_NAMES: [&str; 19] = [
+    "nav",
+    "navbar",
+    "navigation",
+    "menu",
+    "main-menu",
+    "site-nav",
+    "site-navigation",
+    "footer",
+    "site-footer",
+    "page-footer",
+    "sidebar",
+    "site-sidebar",
+    "breadcrumb",
+    "breadcrumbs",
+    "cookie-banner",
+    "cookie-notice",
+    "cookie-consent",
+    "skip-link",
+    "screen-reader-text",
+];
+
+/**
+ * Extracts the text a reader would see as the content of the page: the main element or single
+ * article when the page has one, otherwise the body. Scripts, styles and hidden elements are
+ * left out, along with the navigation, headers, footers and sidebars that repeat on every page.
+ *
+ * Whitespace is collapsed to single spaces. Returns None if the page has no body.
+ */
+pub fn main_text(document: &Html) -> Option<String> {
+    let body = document.select(&Selector::parse("body").unwrap()).next()?;
+    if let Some(main) = main_element(document) {
+        let text = visible_text(main);
+        if !text.is_empty() {
+            return Some(text);
+        }
+    }
+    Some(visible_text(body))
+}
+
+fn main_element(document: &Html) -> Option<ElementRef<'_>> {
+    let selector = Selector::parse("main, [role=main]").unwrap();
+    if let Some(main) = document.select(&selector).next() {
+        return Some(main);
+    }
+    let selector = Selector::parse("article").unwrap();
+    let mut articles = document.select(&selector);
+    match (articles.next(), articles.next()) {
+        (Some(article), None) => Some(article),
+        // a list of articles (a blog index) is the content itself
+        _ => None,
+    }
+}
+
+fn visible_text(root: ElementRef) -> String {
+    let mut words: Vec<&str> = Vec::new();
+    // walk the tree without recursion, deeply nested pages would overflow the stack. The root
+    // itself is never skipped, it was chosen as the content
+    let mut stack: Vec<_> = root.children().rev().collect();
+    while let Some(node) = stack.pop() {
+        match node.value() {
+            Node::Text(text) => words.extend(text.split_whitespace()),
+            Node::Element(_)
+                if ElementRef::wrap(node).is_some_and(|element| !is_skipped(element)) =>
+            {
+                stack.extend(node.children().rev());
+            }
+            _ => {}
+        }
+    }
+    words.join(" ")
+}
+
+fn is_skipped(element: ElementRef) -> bool {
+    let value = element.value();
+    let tag = value.name();
+    if HIDDEN_TAGS.contains(&tag) || BOILERPLATE_TAGS.contains(&tag) {
+        return true;
+    }
+    if value.attr("hidden").is_some() || value.attr("aria-hidden") == Some("true") {
+        return true;
+    }
+    if let Some(style) = value.attr("style") {
+        let style: String = style.split_whitespace().collect::<String>().to_lowercase();
+        if style.contains("display:none") || style.contains("visibility:hidden") {
+            return true;
+        }
+    }
+    if value
+        .attr("role")
+        .is_some_and(|role| BOILERPLATE_ROLES.contains(&role))
+    {
+        return true;
+    }
+    value
+        .classes()
+        .chain(value.id())
+        .any(|name| BOILERPLATE_NAMES.contains(&name.to_lowercase().replace('_', "-").as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(html: &str) -> Option<String> {
+        main_text(&Html::parse_document(html))
+    }
+
+    #[test]
+    fn skips_scripts_styles_and_hidden_elements() {
+        assert_eq!(
+            text(
+                "<html><head><title>t</title><style>body { color: red }</style></head><body>\
+                 <p>Hello <b>world</b></p><script>var tracker = init();</script>\
+                 <div hidden>secret</div><span style=\"display: none\">gone</span>\
+                 <noscript>enable js</noscript><p>again</p></body></html>"
+            ),
+            Some("Hello world again".to_string())
+        );
+    }
+
+    #[test]
+    fn skips_boilerplate() {
+        assert_eq!(
+            text(
+                "<body><nav>Home About</nav><div class=\"site-footer\">Copyright</div>\
+                 <div id=\"sidebar\">Links</div><div role=\"banner\">Logo</div>\
+                 <div class=\"navigator\">Kept</div><p>Content</p><footer>Contact</footer></body>"
+            ),
+            Some("Kept Content".to_string())
+        );
+    }
+
+    #[test]
+    fn keeps_wrappers_named_after_what_they_contain() {
+        assert_eq!(
+            text(
+                "<body class=\"home blog has-sidebar\"><div id=\"page\" class=\"site has-navbar\">\
+                 <div class=\"cookie-recipes\">Baking</div><div class=\"main_menu\">Home</div>\
+                 <p>Post</p></div></body>"
+            ),
+            Some("Baking Post".to_string())
+        );
+    }
+
+    #[test]
+    fn prefers_the_main_content() {
+        assert_eq!(
+            text("<body><div>Menu text</div><main><p>The article</p></main></body>"),
+            Some("The article".to_string())
+        );
+        assert_eq!(
+            text("<body><div>Intro</div><article>One</article><article>Two</article></body>"),
+            Some("Intro One Two".to_string())
+        );
+        // an empty main element doesn't hide the rest of the page
+        assert_eq!(
+            text("<body><p>Text</p><main><script>x()</script></main></body>"),
+            Some("Text".to_string())
+        );
+        assert_eq!(text("<html><head></head></html>").as_deref(), Some(""));
+    }
+}
@@ -0,0 +1,212 @@
+use crate::index_result::now;
+use log::info;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+// items whose history is kept, the ones first seen longest ago are forgotten first
+const MAX_ITEMS: usize = 10_000;
+// events kept per item, the oldest are dropped first
+const MAX_EVENTS: usize = 100;
+
+/**
+ * Why an item was queued
+ */
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnqueuedBy {
+    Api,
+    Seed,
+    Reindex,
+    // a link on this item
+    Link(String),
+    // a redirect on this item that timed out
+    Redirect(String),
+}
+
+/**
+ * How the text of an item was found
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Extractor {
+    // the main content of a web page
+    Html,
+    // a plain text file
+    Text,
+    // anything else, only its file name is indexed
+    FileName,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum CrawlEvent {
+    Enqueued {
+        by: EnqueuedBy,
+    },
+    // the queue was full
+    Dropped {
+        by: EnqueuedBy,
+    },
+    // a request to a gateway, there is one for every gateway tried
+    Fetched {
+        gateway: String,
+        path: String,
+        status: Option<u16>,
+        duration_ms: u64,
+        error: Option<String>,
+    },
+    Indexed {
+        extractor: Extractor,
+        content_type: String,
+        bytes: u64,
+        keywords: usize,
+        links: usize,
+        duration_ms: u64,
+    },
+    Duplicate {
+        of: String,
+        duration_ms: u64,
+    },
+    Failed {
+        reason: String,
+        duration_ms: u64,
+    },
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CrawlRecord {
+    pub time: u64,
+    #[serde(flatten)]
+    pub event: CrawlEvent,
+}
+
+#[derive(Serialize)]
+struct LogLine<'a> {
+    item: &'a str,
+    #[serde(flatten)]
+    record: &'a CrawlRecord,
+}
+
+#[derive(Default)]
+struct History {
+    events: HashMap<String, VecDeque<CrawlRecord>>,
+    // items in the order they were first seen
+    order: VecDeque<String>,
+}
+
+/**
+ * What happened to the items of the crawl: who queued them, the requests made for them and how
+ * indexing them turned out. Every event is also logged as a line of JSON under the "crawl"
+ * target, the history of the most recent items is kept in memory to be looked up.
+ */
+#[derive(Default)]
+pub struct CrawlLog {
+    history: Mutex<History>,
+}
+
+impl CrawlLog {
+    pub fn new() -> Self {
+        CrawlLog::default()
+    }
+
+    pub fn record(&self, item: &str, event: CrawlEvent) {
+        let record = CrawlRecord { time: now(), event };
+        if let Ok(line) = serde_json::to_string(&LogLine {
+            item,
+            record: &record,
+        }) {
+            info!(target: "crawl", "{}", line);
+        }
+
+        let mut history = self.history.lock().unwrap();
+        if !history.events.contains_key(item) {
+            if history.order.len() >= MAX_ITEMS {
+                if let Some(oldest) = history.order.pop_front() {
+                    history.events.remove(&oldest);
+                }
+            }
+            history.order.push_back(item.to_string());
+        }
+        let events = history.events.entry(item.to_string()).or_default();
+        if events.len() >= MAX_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(record);
+    }
+
+    /**
+     * The events recorded for the item, oldest first
+     */
+    pub fn history(&self, item: &str) -> Vec<CrawlRecord> {
+        self.history
+            .lock()
+            .unwrap()
+            .events
+            .get(item)
+            .map_or_else(Vec::new, |events| events.iter().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_latest_events_of_recent_items() {
+        let log = CrawlLog::new();
+        for i in 0..MAX_EVENTS + 1 {
+            log.record(
+                "a",
+                CrawlEvent::Failed {
+                    reason: i.to_string(),
+                    duration_ms: 0,
+                },
+            );
+        }
+        let history = log.history("a");
+        assert_eq!(history.len(), MAX_EVENTS);
+        assert_eq!(
+            history[0].event,
+            CrawlEvent::Failed {
+                reason: "1".to_string(),
+                duration_ms: 0
+            }
+        );
+
+        for i in 0..MAX_ITEMS {
+            log.record(
+                &i.to_string(),
+                CrawlEvent::Enqueued {
+                    by: EnqueuedBy::Api,
+                },
+            );
+        }
+        assert!(log.history("a").is_empty());
+        assert_eq!(log.history("1").len(), 1);
+    }
+
+    #[test]
+    fn events_are_flat_json() {
+        let record = CrawlRecord {
+            time: 10,
+            event: CrawlEvent::Enqueued {
+                by: EnqueuedBy::Link("a/b".to_string()),
+            },
+        };
+        let line = serde_json::to_value(LogLine {
+            item: "a/c",
+            record: &record,
+        })
+        .unwrap();
+        assert_eq!(
+            line,
+            serde_json::json!({
+                "item": "a/c",
+                "time": 10,
+                "event": "enqueued",
+                "by": {"link": "a/b"}
+            })
+        );
+    }
+}
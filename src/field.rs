@@ -0,0 +1,133 @@
+/**
+ * The parts of a page that are indexed separately so they can be weighted differently when
+ * scoring and searched on their own (title:ipfs).
+ *
+ * Every field keeps its own range of positions in the postings: body positions start at 0, title
+ * positions at 1 << 28 and so on. That way the field of an occurrence is known from its position
+ * alone, and phrases never match across two fields.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Field {
+    // all of the visible text of the page
+    Body,
+    Title,
+    // h1 - h6, these are part of the body too
+    Heading,
+    // meta description and keywords
+    Meta,
+    // alt text of images
+    Alt,
+    // text of the links to the item on other pages
+    Anchor,
+}
+
+// positions per field, the six fields fit in a u32
+const FIELD_BITS: u32 = 28;
+
+impl Field {
+    pub const ALL: [Field; 6] = [
+        Field::Body,
+        Field::Title,
+        Field::Heading,
+        Field::Meta,
+        Field::Alt,
+        Field::Anchor,
+    ];
+
+    pub fn parse(name: &str) -> Option<Field> {
+        Field::ALL.into_iter().find(|field| field.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Body => "body",
+            Field::Title => "title",
+            Field::Heading => "heading",
+            Field::Meta => "meta",
+            Field::Alt => "alt",
+            Field::Anchor => "anchor",
+        }
+    }
+
+    /**
+     * Converts a position within the field's text to a position in the postings, None if the
+     * text is too long for the field
+     */
+    pub fn position(self, position: u32) -> Option<u32> {
+        if position >> FIELD_BITS != 0 {
+            return None;
+        }
+        Some(((self as u32) << FIELD_BITS) | position)
+    }
+
+    /**
+     * The field a position in the postings belongs to
+     */
+    pub fn of(position: u32) -> Field {
+        Field::ALL[((position >> FIELD_BITS) as usize).min(Field::ALL.len() - 1)]
+    }
+}
+
+/**
+ * How much an occurrence of a query term in each field counts towards a document's score
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Boosts([f64; 6]);
+
+impl Default for Boosts {
+    fn default() -> Self {
+        // body, title, heading, meta, alt, anchor
+        Boosts([1.0, 3.0, 2.0, 1.5, 1.0, 2.0])
+    }
+}
+
+impl Boosts {
+    pub fn get(&self, field: Field) -> f64 {
+        self.0[field as usize]
+    }
+
+    /**
+     * Sets a boost from a "field=weight" string (title=5), returns false if it isn't one
+     */
+    pub fn set(&mut self, setting: &str) -> bool {
+        let Some((name, weight)) = setting.split_once('=') else {
+            return false;
+        };
+        match (Field::parse(name), weight.parse::<f64>()) {
+            (Some(field), Ok(weight)) if weight >= 0.0 => {
+                self.0[field as usize] = weight;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_by_field() {
+        assert_eq!(Field::Body.position(7), Some(7));
+        let title = Field::Title.position(7).unwrap();
+        assert_eq!(Field::of(title), Field::Title);
+        assert_eq!(Field::of(7), Field::Body);
+        assert_eq!(Field::of(Field::Alt.position(0).unwrap()), Field::Alt);
+        assert_eq!(Field::of(Field::Anchor.position(3).unwrap()), Field::Anchor);
+        assert_eq!(Field::Body.position(1 << 28), None);
+    }
+
+    #[test]
+    fn parses_boosts() {
+        let mut boosts = Boosts::default();
+        assert!(boosts.set("title=5"));
+        assert!(boosts.set("alt=0.5"));
+        assert!(!boosts.set("footer=2"));
+        assert!(!boosts.set("title"));
+        assert!(!boosts.set("meta=-1"));
+        assert_eq!(boosts.get(Field::Title), 5.0);
+        assert_eq!(boosts.get(Field::Alt), 0.5);
+        assert_eq!(boosts.get(Field::Meta), 1.5);
+    }
+}
@@ -0,0 +1,293 @@
+use crate::index_metrics::{Histogram, FETCH_BUCKETS};
+use log::{info, warn};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// number of consecutive failures before a gateway is taken out of rotation
+const MAX_CONSECUTIVE_FAILURES: u32 = 3;
+// how long an unhealthy gateway sits out per consecutive failure, capped at MAX_BACKOFF
+const BACKOFF_STEP: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/**
+ * How the next gateway is picked for a request.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Selection {
+    RoundRobin,
+    LatencyWeighted,
+}
+
+impl Selection {
+    pub fn parse(value: &str) -> Option<Selection> {
+        match value {
+            "round-robin" => Some(Selection::RoundRobin),
+            "latency" | "latency-weighted" => Some(Selection::LatencyWeighted),
+            _ => None,
+        }
+    }
+}
+
+pub struct Gateway {
+    pub host: String,
+    requests: AtomicU64,
+    successes: AtomicU64,
+    failures: AtomicU64,
+    consecutive_failures: AtomicU32,
+    // exponentially weighted moving average of the request latency, 0 until the first success
+    latency_ms: AtomicU64,
+    unhealthy_until: Mutex<Option<Instant>>,
+    // time taken by every request, answered or not
+    pub latency: Histogram,
+}
+
+/**
+ * A snapshot of the counters of a single gateway, used for reporting.
+ */
+#[derive(Clone, Debug, Serialize)]
+pub struct GatewayStats {
+    pub host: String,
+    pub healthy: bool,
+    pub requests: u64,
+    pub successes: u64,
+    pub failures: u64,
+    pub latency_ms: u64,
+}
+
+impl Gateway {
+    fn new(host: String) -> Gateway {
+        Gateway {
+            host,
+            requests: AtomicU64::new(0),
+            successes: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            consecutive_failures: AtomicU32::new(0),
+            latency_ms: AtomicU64::new(0),
+            unhealthy_until: Mutex::new(None),
+            latency: Histogram::new(FETCH_BUCKETS),
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        match *self.unhealthy_until.lock().unwrap() {
+            Some(until) => Instant::now() >= until,
+            None => true,
+        }
+    }
+
+    /**
+     * Weight used by latency weighted selection. Gateways we haven't heard back from yet get the
+     * weight of a 100ms gateway so they are tried early on.
+     */
+    fn weight(&self) -> i64 {
+        let latency = match self.latency_ms.load(Ordering::Relaxed) {
+            0 => 100,
+            latency => latency,
+        };
+        (100_000 / latency).max(1) as i64
+    }
+
+    pub fn stats(&self) -> GatewayStats {
+        GatewayStats {
+            host: self.host.clone(),
+            healthy: self.is_healthy(),
+            requests: self.requests.load(Ordering::Relaxed),
+            successes: self.successes.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            latency_ms: self.latency_ms.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/**
+ * The set of gateways content is fetched through. Keeps track of the health and latency of each
+ * gateway so that requests are spread across them and a failing gateway is skipped.
+ */
+pub struct GatewayPool {
+    gateways: Vec<Gateway>,
+    selection: Selection,
+    next: AtomicUsize,
+    // current weights for smooth weighted round robin (see nginx upstream balancing)
+    current_weights: Mutex<Vec<i64>>,
+}
+
+impl GatewayPool {
+    pub fn new(hosts: Vec<String>, selection: Selection) -> Self {
+        let current_weights = Mutex::new(vec![0; hosts.len()]);
+        GatewayPool {
+            gateways: hosts.into_iter().map(Gateway::new).collect(),
+            selection,
+            next: AtomicUsize::new(0),
+            current_weights,
+        }
+    }
+
+    pub fn get(&self, index: usize) -> &Gateway {
+        &self.gateways[index]
+    }
+
+    pub fn all(&self) -> &[Gateway] {
+        &self.gateways
+    }
+
+    /**
+     * Returns the gateway indexes in the order they should be tried for the next request. The
+     * selected gateway comes first, followed by the other healthy gateways and finally the
+     * unhealthy ones, so a request still goes out if every gateway is currently failing.
+     */
+    pub fn attempt_order(&self) -> Vec<usize> {
+        if self.gateways.is_empty() {
+            return Vec::new();
+        }
+        let first = match self.selection {
+            Selection::RoundRobin => self.select_round_robin(),
+            Selection::LatencyWeighted => self.select_weighted(),
+        };
+        let n = self.gateways.len();
+        let rotated = (0..n).map(|i| (first + i) % n);
+        let (healthy, unhealthy): (Vec<usize>, Vec<usize>) =
+            rotated.partition(|i| self.gateways[*i].is_healthy());
+        healthy.into_iter().chain(unhealthy).collect()
+    }
+
+    fn select_round_robin(&self) -> usize {
+        let n = self.gateways.len();
+        for _ in 0..n {
+            let i = self.next.fetch_add(1, Ordering::Relaxed) % n;
+            if self.gateways[i].is_healthy() {
+                return i;
+            }
+        }
+        self.next.load(Ordering::Relaxed) % n
+    }
+
+    fn select_weighted(&self) -> usize {
+        let mut current = self.current_weights.lock().unwrap();
+        let mut total = 0;
+        let mut best: Option<usize> = None;
+        for (i, gateway) in self.gateways.iter().enumerate() {
+            if !gateway.is_healthy() {
+                continue;
+            }
+            let weight = gateway.weight();
+            current[i] += weight;
+            total += weight;
+            if best.is_none_or(|b| current[i] > current[b]) {
+                best = Some(i);
+            }
+        }
+        match best {
+            Some(i) => {
+                current[i] -= total;
+                i
+            }
+            None => self.select_round_robin(),
+        }
+    }
+
+    pub fn record_success(&self, index: usize, latency: Duration) {
+        let gateway = &self.gateways[index];
+        gateway.requests.fetch_add(1, Ordering::Relaxed);
+        gateway.successes.fetch_add(1, Ordering::Relaxed);
+        gateway.consecutive_failures.store(0, Ordering::Relaxed);
+        *gateway.unhealthy_until.lock().unwrap() = None;
+
+        let sample = (latency.as_millis() as u64).max(1);
+        let previous = gateway.latency_ms.load(Ordering::Relaxed);
+        let average = if previous == 0 {
+            sample
+        } else {
+            (previous * 4 + sample) / 5
+        };
+        gateway.latency_ms.store(average, Ordering::Relaxed);
+    }
+
+    pub fn record_failure(&self, index: usize) {
+        let gateway = &self.gateways[index];
+        gateway.requests.fetch_add(1, Ordering::Relaxed);
+        gateway.failures.fetch_add(1, Ordering::Relaxed);
+        let failures = gateway.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= MAX_CONSECUTIVE_FAILURES {
+            let backoff =
+                (BACKOFF_STEP * (failures - MAX_CONSECUTIVE_FAILURES + 1)).min(MAX_BACKOFF);
+            warn!(
+                "Gateway {} failed {} times in a row, skipping it for {:?}",
+                gateway.host, failures, backoff
+            );
+            *gateway.unhealthy_until.lock().unwrap() = Some(Instant::now() + backoff);
+        } else {
+            info!("Gateway {} failed ({} in a row)", gateway.host, failures);
+        }
+    }
+
+    pub fn stats(&self) -> Vec<GatewayStats> {
+        self.gateways.iter().map(|g| g.stats()).collect()
+    }
+
+    /**
+     * If the link points into /ipfs/ on any of the configured gateways, returns the part after
+     * /ipfs/ (the cid and optional path).
+     */
+    pub fn ipfs_path<'a>(&self, link: &'a str) -> Option<&'a str> {
+        let rest = link
+            .strip_prefix("http://")
+            .or_else(|| link.strip_prefix("https://"))?;
+        self.gateways
+            .iter()
+            .find_map(|g| rest.strip_prefix(g.host.as_str())?.strip_prefix("/ipfs/"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(selection: Selection) -> GatewayPool {
+        GatewayPool::new(vec!["a".to_string(), "b".to_string()], selection)
+    }
+
+    #[test]
+    fn round_robin_alternates() {
+        let pool = pool(Selection::RoundRobin);
+        assert_eq!(pool.attempt_order(), vec![0, 1]);
+        assert_eq!(pool.attempt_order(), vec![1, 0]);
+        assert_eq!(pool.attempt_order(), vec![0, 1]);
+    }
+
+    #[test]
+    fn failing_gateway_is_skipped() {
+        let pool = pool(Selection::RoundRobin);
+        for _ in 0..MAX_CONSECUTIVE_FAILURES {
+            pool.record_failure(0);
+        }
+        assert!(!pool.get(0).is_healthy());
+        assert_eq!(pool.attempt_order(), vec![1, 0]);
+        assert_eq!(pool.attempt_order(), vec![1, 0]);
+
+        pool.record_success(0, Duration::from_millis(10));
+        assert!(pool.get(0).is_healthy());
+    }
+
+    #[test]
+    fn latency_weighted_prefers_fast_gateway() {
+        let pool = pool(Selection::LatencyWeighted);
+        pool.record_success(0, Duration::from_millis(10));
+        pool.record_success(1, Duration::from_millis(1000));
+        let fast = (0..100).filter(|_| pool.attempt_order()[0] == 0).count();
+        assert!(fast > 90);
+    }
+
+    #[test]
+    fn ipfs_path_matches_configured_gateways() {
+        let pool = GatewayPool::new(
+            vec!["ipfs.io".to_string(), "127.0.0.1:8080".to_string()],
+            Selection::RoundRobin,
+        );
+        assert_eq!(pool.ipfs_path("https://ipfs.io/ipfs/Qm/a"), Some("Qm/a"));
+        assert_eq!(pool.ipfs_path("http://127.0.0.1:8080/ipfs/Qm"), Some("Qm"));
+        assert_eq!(pool.ipfs_path("https://example.com/ipfs/Qm"), None);
+        assert_eq!(pool.ipfs_path("A/page.html"), None);
+    }
+}
@@ -0,0 +1,438 @@
+use crate::index_queue::IndexQueue;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+// upper bounds in seconds of the histogram buckets
+pub const FETCH_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+const SEARCH_BUCKETS: &[f64] = &[0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0];
+
+/**
+ * Counts of durations falling in each bucket, as a Prometheus histogram
+ */
+pub struct Histogram {
+    buckets: &'static [f64],
+    // one count per bucket plus one for everything above the last
+    counts: Vec<AtomicU64>,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub fn new(buckets: &'static [f64]) -> Self {
+        Histogram {
+            buckets,
+            counts: (0..=buckets.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = self
+            .buckets
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(self.buckets.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /**
+     * Writes the _bucket, _sum and _count lines, labels go in every line ("" or "gateway=\"a\"")
+     */
+    pub fn write(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            cumulative += count.load(Ordering::Relaxed);
+            let bound = match self.buckets.get(i) {
+                Some(bound) => bound.to_string(),
+                None => "+Inf".to_string(),
+            };
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name, labels, separator, bound, cumulative
+            );
+        }
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", labels)
+        };
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, sum);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, cumulative);
+    }
+}
+
+/**
+ * Counters of what the crawler and the search have been doing, exposed at /metrics
+ */
+pub struct Metrics {
+    indexed: AtomicU64,
+    duplicates: AtomicU64,
+    failed: AtomicU64,
+    bytes_indexed: AtomicU64,
+    // class of error -> times it happened
+    errors: Mutex<BTreeMap<&'static str, u64>>,
+    search_latency: Histogram,
+    // workers running, and the items they are working on with when they started on them
+    workers: AtomicUsize,
+    working_on: Mutex<Vec<(String, Instant)>>,
+    started: Instant,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            indexed: AtomicU64::new(0),
+            duplicates: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            bytes_indexed: AtomicU64::new(0),
+            errors: Mutex::new(BTreeMap::new()),
+            search_latency: Histogram::new(SEARCH_BUCKETS),
+            workers: AtomicUsize::new(0),
+            working_on: Mutex::new(Vec::new()),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    pub fn indexed(&self, bytes: u64) {
+        self.indexed.fetch_add(1, Ordering::Relaxed);
+        self.bytes_indexed.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn duplicate(&self) {
+        self.duplicates.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn error(&self, class: &'static str) {
+        *self.errors.lock().unwrap().entry(class).or_insert(0) += 1;
+    }
+
+    pub fn searched(&self, duration: Duration) {
+        self.search_latency.observe(duration);
+    }
+
+    /**
+     * Counts a worker as running until the returned guard is dropped, which happens when the
+     * worker panics too
+     */
+    pub fn worker_started(&self) -> WorkerGuard<'_> {
+        self.workers.fetch_add(1, Ordering::Relaxed);
+        WorkerGuard { metrics: self }
+    }
+
+    /**
+     * Lists the item as being worked on until the returned guard is dropped
+     */
+    pub fn working_on(&self, item: &str) -> WorkingOn<'_> {
+        self.busy_items().push((item.to_string(), Instant::now()));
+        WorkingOn {
+            metrics: self,
+            item: item.to_string(),
+        }
+    }
+
+    pub fn is_working_on(&self, item: &str) -> bool {
+        self.busy_items().iter().any(|(busy, _)| busy == item)
+    }
+
+    fn done_with(&self, item: &str) {
+        let mut working_on = self.busy_items();
+        if let Some(i) = working_on.iter().position(|(busy, _)| busy == item) {
+            working_on.swap_remove(i);
+        }
+    }
+
+    // the list stays usable after a panic elsewhere, guards are dropped while unwinding
+    fn busy_items(&self) -> MutexGuard<'_, Vec<(String, Instant)>> {
+        self.working_on
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /**
+     * Number of workers running and the number of them working on an item
+     */
+    pub fn workers(&self) -> (usize, usize) {
+        (
+            self.workers.load(Ordering::Relaxed),
+            self.busy_items().len(),
+        )
+    }
+
+    /**
+     * The items being worked on and for how long, longest first
+     */
+    pub fn in_flight(&self) -> Vec<(String, Duration)> {
+        let mut items: Vec<(String, Duration)> = self
+            .busy_items()
+            .iter()
+            .map(|(item, since)| (item.clone(), since.elapsed()))
+            .collect();
+        items.sort_by_key(|(_, elapsed)| std::cmp::Reverse(*elapsed));
+        items
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+#[must_use]
+pub struct WorkerGuard<'a> {
+    metrics: &'a Metrics,
+}
+
+impl Drop for WorkerGuard<'_> {
+    fn drop(&mut self) {
+        self.metrics.workers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[must_use]
+pub struct WorkingOn<'a> {
+    metrics: &'a Metrics,
+    item: String,
+}
+
+impl Drop for WorkingOn<'_> {
+    fn drop(&mut self) {
+        self.metrics.done_with(&self.item);
+    }
+}
+
+/**
+ * What kind of failure a request error is, for counting errors by class
+ */
+pub fn error_class(err: &reqwest::Error) -> &'static str {
+    if err.is_timeout() {
+        "timeout"
+    } else if err.is_connect() {
+        "connect"
+    } else if let Some(status) = err.status() {
+        if status.is_client_error() {
+            "http_4xx"
+        } else {
+            "http_5xx"
+        }
+    } else if err.is_body() || err.is_decode() {
+        "body"
+    } else {
+        "request"
+    }
+}
+
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+impl IndexQueue {
+    /**
+     * The metrics in the Prometheus text format
+     */
+    pub fn metrics_text(&self) -> String {
+        let metrics = &self.metrics;
+        let mut out = String::new();
+
+        write_metric(
+            &mut out,
+            "ipfs_indexer_items_total",
+            "counter",
+            "Items taken off the queue, by outcome",
+        );
+        for (outcome, count) in [
+            ("indexed", &metrics.indexed),
+            ("duplicate", &metrics.duplicates),
+            ("failed", &metrics.failed),
+        ] {
+            let _ = writeln!(
+                out,
+                "ipfs_indexer_items_total{{outcome=\"{}\"}} {}",
+                outcome,
+                count.load(Ordering::Relaxed)
+            );
+        }
+        write_metric(
+            &mut out,
+            "ipfs_indexer_indexed_bytes_total",
+            "counter",
+            "Bytes of content indexed",
+        );
+        let _ = writeln!(
+            out,
+            "ipfs_indexer_indexed_bytes_total {}",
+            metrics.bytes_indexed.load(Ordering::Relaxed)
+        );
+        write_metric(
+            &mut out,
+            "ipfs_indexer_errors_total",
+            "counter",
+            "Errors fetching and indexing content, by class",
+        );
+        for (class, count) in metrics.errors.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "ipfs_indexer_errors_total{{class=\"{}\"}} {}",
+                class, count
+            );
+        }
+
+        write_metric(
+            &mut out,
+            "ipfs_indexer_fetch_duration_seconds",
+            "histogram",
+            "Time taken by requests to each gateway",
+        );
+        for gateway in self.gateways.all() {
+            gateway.latency.write(
+                &mut out,
+                "ipfs_indexer_fetch_duration_seconds",
+                &format!("gateway=\"{}\"", gateway.host),
+            );
+        }
+        write_metric(
+            &mut out,
+            "ipfs_indexer_search_duration_seconds",
+            "histogram",
+            "Time taken by searches",
+        );
+        metrics
+            .search_latency
+            .write(&mut out, "ipfs_indexer_search_duration_seconds", "");
+
+        let (workers, busy) = metrics.workers();
+        for (name, help, value) in [
+            (
+                "ipfs_indexer_queue_length",
+                "Items waiting to be indexed",
+                self.queue_length(),
+            ),
+            (
+                "ipfs_indexer_index_size",
+                "Items in the index",
+                self.index_length(),
+            ),
+            (
+                "ipfs_indexer_keywords",
+                "Distinct keywords in the index",
+                self.keyword_length(),
+            ),
+        ] {
+            write_metric(&mut out, name, "gauge", help);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+        write_metric(
+            &mut out,
+            "ipfs_indexer_workers",
+            "gauge",
+            "Crawl workers, by state",
+        );
+        let _ = writeln!(out, "ipfs_indexer_workers{{state=\"busy\"}} {}", busy);
+        let _ = writeln!(
+            out,
+            "ipfs_indexer_workers{{state=\"idle\"}} {}",
+            workers.saturating_sub(busy)
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let histogram = Histogram::new(&[0.1, 1.0]);
+        histogram.observe(Duration::from_millis(50));
+        histogram.observe(Duration::from_millis(500));
+        histogram.observe(Duration::from_secs(2));
+        let mut out = String::new();
+        histogram.write(&mut out, "latency", "gateway=\"a\"");
+        assert_eq!(
+            out,
+            "latency_bucket{gateway=\"a\",le=\"0.1\"} 1\n\
+             latency_bucket{gateway=\"a\",le=\"1\"} 2\n\
+             latency_bucket{gateway=\"a\",le=\"+Inf\"} 3\n\
+             latency_sum{gateway=\"a\"} 2.55\n\
+             latency_count{gateway=\"a\"} 3\n"
+        );
+    }
+
+    #[test]
+    fn tracks_the_items_being_worked_on() {
+        let metrics = Metrics::new();
+        let _first = metrics.worker_started();
+        let second = metrics.worker_started();
+        let a = metrics.working_on("a");
+        let _b = metrics.working_on("b");
+        drop(a);
+        assert_eq!(metrics.workers(), (2, 1));
+        let in_flight: Vec<String> = metrics
+            .in_flight()
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect();
+        assert_eq!(in_flight, vec!["b"]);
+        drop(second);
+        assert_eq!(metrics.workers(), (1, 1));
+    }
+
+    #[test]
+    fn workers_that_panic_are_accounted_for() {
+        let metrics = Metrics::new();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _worker = metrics.worker_started();
+            let _working_on = metrics.working_on("a");
+            panic!("the worker failed");
+        }));
+        assert!(result.is_err());
+        assert_eq!(metrics.workers(), (0, 0));
+        assert!(metrics.in_flight().is_empty());
+    }
+
+    #[test]
+    fn exposes_crawl_and_index_metrics() {
+        let queue = IndexQueue::new();
+        queue.metrics.indexed(1000);
+        queue.metrics.duplicate();
+        queue.metrics.error("timeout");
+        queue.metrics.error("timeout");
+        let _worker = queue.metrics.worker_started();
+        queue.metrics.searched(Duration::from_millis(3));
+        queue
+            .gateways
+            .get(0)
+            .latency
+            .observe(Duration::from_millis(200));
+
+        let text = queue.metrics_text();
+        assert!(text.contains("ipfs_indexer_items_total{outcome=\"indexed\"} 1\n"));
+        assert!(text.contains("ipfs_indexer_items_total{outcome=\"duplicate\"} 1\n"));
+        assert!(text.contains("ipfs_indexer_indexed_bytes_total 1000\n"));
+        assert!(text.contains("ipfs_indexer_errors_total{class=\"timeout\"} 2\n"));
+        assert!(text.contains(
+            "ipfs_indexer_fetch_duration_seconds_bucket{gateway=\"ipfs.io\",le=\"0.25\"} 1\n"
+        ));
+        assert!(text.contains("ipfs_indexer_search_duration_seconds_count 1\n"));
+        assert!(text.contains("ipfs_indexer_queue_length 0\n"));
+        assert!(text.contains("ipfs_indexer_workers{state=\"idle\"} 1\n"));
+    }
+}
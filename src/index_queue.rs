@@ -1,11 +1,39 @@
+use crate::analyzer::Analyzer;
+use crate::click_log::ClickLog;
+use crate::config::Config;
+use crate::content;
+use crate::crawl_log::{CrawlEvent, CrawlLog, EnqueuedBy, Extractor};
+use crate::field::{Boosts, Field};
+use crate::gateway::GatewayPool;
+use crate::index_metrics::{error_class, Metrics};
 use crate::index_result::IndexResult;
-use actix_web::body::MessageBody;
+use crate::index_suggest::QueryLog;
+use crate::keyword_rank::KeywordRank;
+use crate::language;
+use crate::link_graph::{changed_targets, Link, LinkGraph};
+use crate::memory::MemoryBudget;
+use crate::seeds::SeedList;
+use crate::simhash;
+use crate::word_forms::{WordForms, Words};
+use cid::Cid;
 use crossbeam_queue::ArrayQueue;
-use dashmap::{DashMap, Map};
-use log::{info, trace, warn};
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use log::{debug, info, trace, warn};
+use reqwest::blocking::Response;
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
 use scraper::{Html, Selector};
+use serde::Serialize;
 use std::collections::HashMap;
-use std::ops::Not;
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::Instant;
+
+// distinct link texts indexed for an item, enough to describe it without letting a heavily
+// linked item's anchor field grow without bound
+const MAX_ANCHORS: usize = 50;
+// bytes of a text file that are read and indexed, the rest of a bigger file is left out
+const MAX_TEXT_BYTES: u64 = 4 * 1024 * 1024;
 
 pub struct IndexQueue {
     // queue of items to index
@@ -14,37 +42,480 @@ pub struct IndexQueue {
 
     // index results (cid -> result)
     pub map: DashMap<String, IndexResult>,
+    // content cid -> the item in map it was indexed as
+    pub contents: DashMap<String, String>,
+    // items with the same content as an item in map -> that item
+    pub aliases: DashMap<String, String>,
+    // items removed from the index, they aren't queued or stored again unless they are re-indexed.
+    // Only kept in memory, after a restart they can be found again
+    pub removed: DashMap<String, ()>,
+
+    // used for searching. Maps keyword to the cids containing it and the positions of the keyword
+    // in each of them
+    pub keywords: DashMap<String, HashMap<String, Vec<u32>>>,
+    // used to rank the keywords by the number of cids in the map above. It is only updated while
+    // the keyword's entry in keywords is locked so the two always agree
+    pub keyword_rank: KeywordRank,
+    // the words stemmed terms are written as, to show terms to people
+    pub word_forms: WordForms,
+
+    // gateways content is fetched through
+    pub gateways: GatewayPool,
+
+    // cids the crawl starts from, re-crawled periodically
+    pub seeds: SeedList,
+
+    // turns page content and queries into keywords
+    pub analyzer: Analyzer,
+    // weight of a match in each field when scoring search results
+    pub boosts: Boosts,
+
+    // searches that found something, for suggestions
+    pub query_log: QueryLog,
+    // search results people clicked on, to rank popular results higher
+    pub clicks: ClickLog,
+    // the gateway clicked results are sent to, without a trailing slash
+    pub public_gateway: String,
+
+    // links between the indexed items
+    pub links: LinkGraph,
+
+    // keeps the index within the configured memory, by moving parts of it to disk
+    pub memory: MemoryBudget,
+
+    // counters exposed at /metrics
+    pub metrics: Metrics,
+    // what happened to every item of the crawl, to tell why something was or wasn't indexed
+    pub crawl_log: CrawlLog,
+
+    // where seeds, links and evicted data are kept
+    pub data_dir: Option<PathBuf>,
+}
+
+/**
+ * Outcome of asking for an item to be queued
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnqueueStatus {
+    Queued,
+    AlreadyIndexed,
+    AlreadyQueued,
+    QueueFull,
+    Invalid,
+    Removed,
+}
+
+/**
+ * How far along an item is in the crawl
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CrawlStatus {
+    Indexed,
+    Queued,
+    NotIndexed,
+}
+
+// keyword -> positions of the keyword in a document
+pub type Positions = HashMap<String, Vec<u32>>;
+
+/**
+ * What indexing an item produces
+ */
+pub struct Document {
+    pub result: IndexResult,
+    pub positions: Positions,
+    pub links: Vec<Link>,
+    pub extractor: Extractor,
+    // the words the terms were stemmed from
+    pub words: Words,
+}
+
+/**
+ * What fetching an item gives
+ */
+enum Retrieved {
+    Document(Box<Document>),
+    // the content is already indexed as this item
+    Duplicate(String),
+}
 
-    // used for searching. Maps keyword to unique set of cids
-    pub keywords: DashMap<String, DashMap<String, ()>>,
-    // used to rank the keywords. Everytime keywords is updated, rank should be updated with the
-    // number of cids in the dashmap above
-    pub keyword_rank: DashMap<String, u32>,
+/**
+ * Queue items are a cid optionally followed by a path inside it (cid/path/to/file)
+ */
+pub fn is_valid_item(item: &str) -> bool {
+    let root = item.split('/').next().unwrap_or("");
+    Cid::try_from(root).is_ok()
 }
 
 impl IndexQueue {
+    // the binary always builds the queue from a Config, the tests use the defaults
+    #[cfg(test)]
     pub fn new() -> Self {
+        IndexQueue::with_config(&Config {
+            data_dir: None,
+            ..Config::default()
+        })
+    }
+
+    pub fn with_config(config: &Config) -> Self {
+        let mut analyzer = Analyzer::new(config.min_token_length, config.max_token_length);
+        if let Some(path) = &config.stop_words_file {
+            if let Err(err) = analyzer.load_stop_words(path) {
+                warn!("Error loading stop words from {}: {}", path.display(), err);
+            }
+        }
         IndexQueue {
-            queue: ArrayQueue::new(1000),
+            queue: ArrayQueue::new(config.queue_capacity),
             queue_set: DashMap::new(),
             map: DashMap::new(),
+            contents: DashMap::new(),
+            aliases: DashMap::new(),
+            removed: DashMap::new(),
             keywords: DashMap::new(),
-            keyword_rank: DashMap::new(),
+            keyword_rank: KeywordRank::new(),
+            word_forms: WordForms::new(),
+            gateways: GatewayPool::new(config.gateways.clone(), config.selection),
+            seeds: SeedList::new(config.data_dir.as_ref().map(|dir| dir.join("seeds.txt"))),
+            analyzer,
+            boosts: config.boosts.clone(),
+            query_log: QueryLog::new(),
+            clicks: ClickLog::new(),
+            public_gateway: config.public_gateway.trim_end_matches('/').to_string(),
+            links: LinkGraph::new(config.data_dir.as_ref().map(|dir| dir.join("links.jsonl"))),
+            memory: MemoryBudget::new(config.memory_budget, config.data_dir.as_deref()),
+            metrics: Metrics::new(),
+            crawl_log: CrawlLog::new(),
+            data_dir: config.data_dir.clone(),
         }
     }
 
-    pub fn enqueue(&self, item: String) {
-        if self.map.contains_key(&item) {
+    pub fn enqueue(&self, item: String, by: EnqueuedBy) -> EnqueueStatus {
+        if self.map.contains_key(&item) || self.aliases.contains_key(&item) {
             trace!("Already indexed {}", item);
-            return;
+            return EnqueueStatus::AlreadyIndexed;
         }
+        self.push(item, by)
+    }
 
-        if self.queue_set.contains_key(&*item) {
-            info!("{} already in queue", item.clone());
+    /**
+     * Puts the item on the queue unless it is already queued, even if it has been indexed before
+     */
+    fn push(&self, item: String, by: EnqueuedBy) -> EnqueueStatus {
+        if self.removed.contains_key(&item) {
+            trace!("{} was removed from the index", item);
+            EnqueueStatus::Removed
+        } else if self.queue_set.contains_key(&*item) {
+            trace!("{} already in queue", item);
+            EnqueueStatus::AlreadyQueued
         } else {
-            info!("Enqueuing {}", item);
-            let _ = self.queue.push(item.clone());
+            debug!("Enqueuing {}", item);
+            if self.queue.push(item.clone()).is_err() {
+                warn!("Queue is full, dropping {}", item);
+                self.crawl_log.record(&item, CrawlEvent::Dropped { by });
+                return EnqueueStatus::QueueFull;
+            }
             self.queue_set.insert(item.clone(), ());
+            self.crawl_log.record(&item, CrawlEvent::Enqueued { by });
+            EnqueueStatus::Queued
+        }
+    }
+
+    /**
+     * Queues every seed again so that updated content behind them gets picked up
+     */
+    pub fn reseed(&self) {
+        for seed in self.seeds.list() {
+            self.push(seed, EnqueuedBy::Seed);
+        }
+    }
+
+    /**
+     * Queues an item to be fetched and indexed again even if it is already in the index, or was
+     * removed from it. The new result replaces the old one once it has been retrieved.
+     */
+    pub fn reindex(&self, item: String) -> EnqueueStatus {
+        self.removed.remove(&item);
+        self.push(item, EnqueuedBy::Reindex)
+    }
+
+    /**
+     * Adds the result to the index under the item it was queued as, replacing any previous
+     * result for the item
+     */
+    fn store(&self, item: String, document: Document) {
+        if self.removed.contains_key(&item) {
+            info!(
+                "{} was removed while it was being fetched, not storing it",
+                item
+            );
+            return;
+        }
+        // another worker may have indexed the same content under another path meanwhile
+        if let Some(content_cid) = &document.result.content_cid {
+            let indexed = match self.contents.entry(content_cid.clone()) {
+                Entry::Occupied(entry)
+                    if *entry.get() != item && self.map.contains_key(entry.get()) =>
+                {
+                    Some(entry.get().clone())
+                }
+                entry => {
+                    entry.insert(item.clone());
+                    None
+                }
+            };
+            if let Some(indexed) = indexed {
+                info!("{} has the same content as {}", item, indexed);
+                self.add_alias(&item, &indexed);
+                return;
+            }
+        }
+        // it has content of its own now
+        self.unalias(&item);
+        let Document {
+            mut result,
+            mut positions,
+            links,
+            words,
+            ..
+        } = document;
+        let previous = self.links.set_links(&item, links);
+        let changed = changed_targets(&previous, &self.links.outlinks(&item));
+        // holding the entry keeps other workers from storing or removing the same item until the
+        // keywords have been updated. Locks are always taken in the order map -> keywords ->
+        // keyword_rank, and nothing holds a keywords guard while looking into map.
+        let entry = self.map.entry(item.clone());
+        // read the anchors while holding the entry, a page linking here that is stored meanwhile
+        // refreshes them once the entry is released
+        self.index_anchors(&item, &mut result, &mut positions);
+        // the content the item had before, when it changed
+        let mut replaced = None;
+        match entry {
+            Entry::Occupied(mut entry) => {
+                result.first_seen = entry.get().first_seen;
+                if entry.get().content_cid == result.content_cid {
+                    result.aliases = entry.get().aliases.clone();
+                } else {
+                    replaced = Some((entry.get().content_cid.clone(), entry.get().aliases.clone()));
+                }
+                self.memory.forget_body(&item);
+                self.unlink_keywords(&item, entry.get());
+                self.link_keywords(&item, positions);
+                entry.insert(result);
+            }
+            Entry::Vacant(entry) => {
+                self.link_keywords(&item, positions);
+                entry.insert(result);
+            }
+        }
+        if let Some((content_cid, aliases)) = replaced {
+            // the old content isn't indexed anywhere now, other paths to it may be indexed again
+            if let Some(content_cid) = content_cid {
+                self.contents
+                    .remove_if(&content_cid, |_, indexed| *indexed == item);
+            }
+            // the aliases had the old content, they have to be fetched again to index it
+            for alias in aliases {
+                if self
+                    .aliases
+                    .remove_if(&alias, |_, indexed| *indexed == item)
+                    .is_some()
+                {
+                    self.push(alias, EnqueuedBy::Reindex);
+                }
+            }
+        }
+        self.word_forms.add(&words);
+        for target in changed {
+            self.refresh_anchors(&target);
+        }
+        // removed after the check above, and the removal may have come before the result was in
+        if self.removed.contains_key(&item) {
+            self.unindex(&item);
+        }
+    }
+
+    /**
+     * The item content with this cid was indexed as, unless that is the item itself
+     */
+    fn indexed_as(&self, content_cid: &str, item: &str) -> Option<String> {
+        let indexed = self.contents.get(content_cid)?.clone();
+        (indexed != item && self.map.contains_key(&indexed)).then_some(indexed)
+    }
+
+    /**
+     * Records that the alias has the same content as the indexed item, instead of indexing it
+     * again. An earlier result of the alias itself is removed.
+     */
+    fn add_alias(&self, alias: &str, indexed: &str) {
+        if self.removed.contains_key(alias) {
+            return;
+        }
+        self.unindex(alias);
+        let Some(mut result) = self.map.get_mut(indexed) else {
+            return;
+        };
+        if !result.aliases.iter().any(|known| known == alias) {
+            result.aliases.push(alias.to_string());
+        }
+        self.aliases.insert(alias.to_string(), indexed.to_string());
+        drop(result);
+        if self.removed.contains_key(alias) {
+            self.unalias(alias);
+        }
+    }
+
+    /**
+     * Forgets that the item is an alias, returning the item it was an alias of
+     */
+    fn unalias(&self, alias: &str) -> Option<String> {
+        let (_, indexed) = self.aliases.remove(alias)?;
+        if let Some(mut result) = self.map.get_mut(&indexed) {
+            result.aliases.retain(|known| known != alias);
+        }
+        Some(indexed)
+    }
+
+    /**
+     * Removes the item from the index and keeps it from being queued or stored again, until it is
+     * re-indexed. Returns the result it was indexed with. Items that are neither indexed, queued
+     * nor being fetched are left alone, there is nothing to keep out.
+     */
+    pub fn remove(&self, item: &str) -> Option<IndexResult> {
+        if self.crawl_status(item) == CrawlStatus::NotIndexed && !self.metrics.is_working_on(item) {
+            return None;
+        }
+        // marked first, so a worker storing the item meanwhile takes it out again
+        self.removed.insert(item.to_string(), ());
+        self.queue_set.remove(item);
+        self.unindex(item)
+    }
+
+    /**
+     * Takes the item out of the index along with its entries in the keyword maps. Taking out an
+     * alias only forgets the alias and returns the result it was an alias of, taking out the item
+     * it is an alias of removes its aliases too.
+     */
+    fn unindex(&self, item: &str) -> Option<IndexResult> {
+        if let Some(indexed) = self.unalias(item) {
+            return self.map.get(&indexed).map(|result| result.clone());
+        }
+        let (result, links) = match self.map.entry(item.to_string()) {
+            Entry::Occupied(entry) => {
+                self.unlink_keywords(item, entry.get());
+                let links = self.links.remove(item);
+                self.memory.forget_body(item);
+                (entry.remove(), links)
+            }
+            Entry::Vacant(_) => return None,
+        };
+        for target in changed_targets(&links, &[]) {
+            self.refresh_anchors(&target);
+        }
+        if let Some(content_cid) = &result.content_cid {
+            self.contents
+                .remove_if(content_cid, |_, indexed| indexed == item);
+        }
+        for alias in &result.aliases {
+            self.aliases.remove(alias);
+        }
+        Some(result)
+    }
+
+    /**
+     * Adds the text of the links to the item to its keywords and positions. The anchor field
+     * comes last, so the positions stay sorted.
+     */
+    fn index_anchors(&self, item: &str, result: &mut IndexResult, positions: &mut Positions) {
+        let anchors = self.links.anchors(item, MAX_ANCHORS).join("\n");
+        let (anchor_keywords, anchor_positions, _) =
+            self.index_fields(vec![(Field::Anchor, anchors)], result.lang.as_deref());
+        for (word, count) in &anchor_keywords {
+            *result.keywords.entry(word.clone()).or_insert(0) += count;
+        }
+        result.anchor_keywords = anchor_keywords;
+        for (word, word_positions) in anchor_positions {
+            positions.entry(word).or_default().extend(word_positions);
+        }
+    }
+
+    /**
+     * Re-indexes the anchor field of an indexed item after the links to it have changed
+     */
+    fn refresh_anchors(&self, item: &str) {
+        // must not be called while holding an entry of map, the item may be in the same shard
+        let Some(mut result) = self.map.get_mut(item) else {
+            return;
+        };
+        // take out the old anchor positions. Only the postings of words from the anchors are
+        // looked at, the others may have been evicted and would be read back in for nothing
+        let anchor_keywords = std::mem::take(&mut result.anchor_keywords);
+        for (word, removed) in anchor_keywords {
+            if let Some(count) = result.keywords.get_mut(&word) {
+                *count -= removed.min(*count);
+                if *count == 0 {
+                    result.keywords.remove(&word);
+                }
+            }
+            let Entry::Occupied(mut cids) = self.keyword_entry(&word) else {
+                continue;
+            };
+            let Some(word_positions) = cids.get_mut().get_mut(item) else {
+                continue;
+            };
+            word_positions.retain(|position| Field::of(*position) != Field::Anchor);
+            if word_positions.is_empty() {
+                cids.get_mut().remove(item);
+                let remaining = cids.get().len();
+                self.keyword_rank.set(&word, remaining as u32);
+                if remaining == 0 {
+                    cids.remove();
+                    self.memory.forget_keyword(&word);
+                }
+            }
+        }
+        // and put in the new ones
+        let mut positions = Positions::new();
+        self.index_anchors(item, &mut result, &mut positions);
+        for (word, word_positions) in positions {
+            let mut cids = self.keyword_entry(&word).or_default();
+            cids.entry(item.to_string())
+                .or_default()
+                .extend(word_positions);
+            self.keyword_rank.set(&word, cids.len() as u32);
+        }
+    }
+
+    fn link_keywords(&self, item: &str, positions: Positions) {
+        for (word, word_positions) in positions {
+            let mut cids = self.keyword_entry(&word).or_default();
+            cids.insert(item.to_string(), word_positions);
+            self.keyword_rank.set(&word, cids.len() as u32);
+        }
+    }
+
+    fn unlink_keywords(&self, item: &str, result: &IndexResult) {
+        for word in result.keywords.keys() {
+            if let Entry::Occupied(mut cids) = self.keyword_entry(word) {
+                cids.get_mut().remove(item);
+                let remaining = cids.get().len();
+                self.keyword_rank.set(word, remaining as u32);
+                if remaining == 0 {
+                    cids.remove();
+                    self.memory.forget_keyword(word);
+                }
+            }
+        }
+    }
+
+    pub fn crawl_status(&self, item: &str) -> CrawlStatus {
+        if self.map.contains_key(item) || self.aliases.contains_key(item) {
+            CrawlStatus::Indexed
+        } else if self.queue_set.contains_key(item) {
+            CrawlStatus::Queued
+        } else {
+            CrawlStatus::NotIndexed
         }
     }
 
@@ -57,54 +528,72 @@ impl IndexQueue {
     }
 
     pub fn keyword_length(&self) -> usize {
-        self.keywords.len()
+        self.keyword_rank.len()
     }
 
     /*
      * Returns the top n keywords by the number of CIDs they map to
      */
     pub fn top_keywords(&self, n: usize) -> Vec<(String, u32)> {
-        let all_keywords_iter = self.keyword_rank.clone().into_iter();
-        let mut all_keywords: Vec<(String, u32)> = all_keywords_iter.collect();
-        all_keywords.sort_by(|a, b| b.1.cmp(&a.1));
-        all_keywords.iter().take(n).cloned().collect()
+        self.keyword_rank.top(n)
     }
 
-    pub fn search(&self, query: String) -> Vec<IndexResult> {
-        let mut results = Vec::new();
-
-        // for the search, we could iterate through all of the indexed results and then search
-        // each result for a keyword, or instead we could do a keyword lookup in the keyword map
-        // which will give us a list of CIDs that contain the keyword.
-
-        if self.keywords.contains_key(&query) {
-            let cid_map: DashMap<String, ()> = self.keywords.get(&query).unwrap().clone();
-            cid_map.into_iter().for_each(|(cid, _)| {
-                if self.map.contains_key(&cid) {
-                    let index_result: IndexResult = self.map.get(&cid).unwrap().clone();
-                    results.push(index_result);
+    pub fn start(&self) {
+        // dropped when the worker stops, or panics
+        let _worker = self.metrics.worker_started();
+        loop {
+            if let Some(item) = self.queue.pop() {
+                // listed before it leaves queue_set, so a removal always finds it in one of them
+                let _working_on = self.metrics.working_on(&item);
+                self.queue_set.remove(&*item);
+                if self.removed.contains_key(&item) {
+                    trace!("{} was removed from the index, skipping it", item);
+                    continue;
                 }
-            });
-        }
+                info!("Indexing {}", item);
+                let start = Instant::now();
 
-        return results;
-    }
+                let result = self.retrieve_content(item.clone());
 
-    pub fn start(&self, gateway: String) {
-        loop {
-            if self.queue.is_empty() == false {
-                let try_item = self.queue.pop();
-                if try_item.is_some() {
-                    let item = try_item.unwrap();
-                    self.queue_set.remove(&*item);
-                    warn!("Indexing {}", item);
-
-                    let result = self.retrieve_content(gateway.clone(), item.clone());
-
-                    if result.is_some() {
-                        self.map.insert(item.clone(), result.unwrap());
-                    } else {
-                        warn!("Error retrieving CID {}", item);
+                let duration_ms = start.elapsed().as_millis() as u64;
+                match result {
+                    Ok(Retrieved::Document(document)) => {
+                        self.metrics.indexed(document.result.size);
+                        self.crawl_log.record(
+                            &item,
+                            CrawlEvent::Indexed {
+                                extractor: document.extractor,
+                                content_type: document.result.content_type.clone(),
+                                bytes: document.result.size,
+                                keywords: document.result.keywords.len(),
+                                links: document.links.len(),
+                                duration_ms,
+                            },
+                        );
+                        self.store(item.clone(), *document);
+                    }
+                    Ok(Retrieved::Duplicate(indexed)) => {
+                        info!("{} has the same content as {}", item, indexed);
+                        self.metrics.duplicate();
+                        self.add_alias(&item, &indexed);
+                        self.crawl_log.record(
+                            &item,
+                            CrawlEvent::Duplicate {
+                                of: indexed,
+                                duration_ms,
+                            },
+                        );
+                    }
+                    Err(reason) => {
+                        warn!("Error retrieving CID {}: {}", item, reason);
+                        self.metrics.failed();
+                        self.crawl_log.record(
+                            &item,
+                            CrawlEvent::Failed {
+                                reason,
+                                duration_ms,
+                            },
+                        );
                         // self.enqueue(item.clone()); // for now give up on error
                     }
                 }
@@ -113,200 +602,1341 @@ impl IndexQueue {
     }
 
     /**
-     * Use the http client to obtain the page from the ipfs gateway. If there is a failure to
-     * obtain the CID, we give up for now.
+     * Request the cid (and optional path) from the gateways. The selected gateway is tried first,
+     * on a connection error or a server error we fail over to the next gateway. Returns the last
+     * error if every gateway failed. Every request is recorded in the crawl log of the item.
      */
-    fn retrieve_content(&self, gateway: String, cid: String) -> Option<IndexResult> {
-        let url = format!("http://{}/ipfs/{}", gateway, cid);
-        warn!("Retreiving {}", url);
+    fn fetch(&self, item: &str, path: &str) -> Result<Response, reqwest::Error> {
         let client = reqwest::blocking::Client::new();
-        let result = client.get(&url).send();
+        let mut last_error = None;
+        for index in self.gateways.attempt_order() {
+            let host = &self.gateways.get(index).host;
+            let url = format!("http://{}/ipfs/{}", host, path);
+            debug!("Retrieving {}", url);
+            let start = Instant::now();
+            let result = client.get(&url).send().and_then(|r| r.error_for_status());
+            self.gateways.get(index).latency.observe(start.elapsed());
+            if let Err(err) = &result {
+                self.metrics.error(error_class(err));
+            }
+            self.crawl_log.record(
+                item,
+                CrawlEvent::Fetched {
+                    gateway: host.clone(),
+                    path: path.to_string(),
+                    status: match &result {
+                        Ok(response) => Some(response.status().as_u16()),
+                        Err(err) => err.status().map(|status| status.as_u16()),
+                    },
+                    duration_ms: start.elapsed().as_millis() as u64,
+                    error: result.as_ref().err().map(|err| err.to_string()),
+                },
+            );
+            match result {
+                Ok(response) => {
+                    self.gateways.record_success(index, start.elapsed());
+                    return Ok(response);
+                }
+                Err(err) if err.status().is_some_and(|s| s.is_client_error()) => {
+                    // the gateway is fine, the content just isn't there
+                    self.gateways.record_success(index, start.elapsed());
+                    return Err(err);
+                }
+                Err(err) => {
+                    warn!(
+                        "Error: {} from gateway {}",
+                        err,
+                        self.gateways.get(index).host
+                    );
+                    self.gateways.record_failure(index);
+                    last_error = Some(err);
+                }
+            }
+        }
+        Err(last_error.expect("no gateways configured"))
+    }
+
+    /**
+     * Use the http client to obtain the page from the ipfs gateways. If there is a failure to
+     * obtain the CID, we give up for now and return why.
+     */
+    fn retrieve_content(&self, cid: String) -> Result<Retrieved, String> {
+        let result = self.fetch(&cid, &cid);
         let response = match result {
             Ok(result) => result,
             Err(err) => {
-                warn!("Error: {}, not re-enqueue-ing cid", err);
                 // self.enqueue(cid.clone()); // for now give up on error
-                return None;
+                return Err(format!("fetch: {}", err));
             }
         };
 
-        // todo: check the file type header and only proceed this way if it is actually html, otherwise index as some other file type
-        // plus some meta data
-        let html = response.text().unwrap();
-        let mut document = Html::parse_document(html.as_str());
-        let result = self.detect_redirect(url, cid.clone(), document.clone());
+        // the headers come before the body, so content indexed under another path isn't
+        // downloaded again
+        let content_cid = content_cid(response.headers());
+        if let Some(indexed) = content_cid
+            .as_ref()
+            .and_then(|content_cid| self.indexed_as(content_cid, &cid))
+        {
+            return Ok(Retrieved::Duplicate(indexed));
+        }
+
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(mime_type)
+            .unwrap_or_else(|| "text/html".to_string());
+        if !is_html(&content_type) {
+            // only the name of anything but text is indexed, its body isn't downloaded at all
+            let length = response.content_length();
+            let mut bytes = Vec::new();
+            if is_text(&content_type) {
+                if let Err(err) = response.take(MAX_TEXT_BYTES).read_to_end(&mut bytes) {
+                    self.metrics.error("body");
+                    return Err(format!("body: {}", err));
+                }
+            }
+            let size = length.unwrap_or(bytes.len() as u64);
+            let mut document = self.process_file(cid, content_type, &bytes, size);
+            document.result.content_cid = content_cid;
+            return Ok(Retrieved::Document(Box::new(document)));
+        }
+
+        let mut html = response.text().map_err(|err| {
+            self.metrics.error(error_class(&err));
+            format!("body: {}", err)
+        })?;
         let mut fullcid = cid.clone();
-        if result.is_some() {
-            (fullcid, document) = result.unwrap();
+        if let Some(redirect) = self.detect_redirect(cid.clone(), Html::parse_document(&html))? {
+            (fullcid, html) = redirect;
         }
         trace!("received: {:?}", html.as_str());
 
-        self.process_content(gateway, fullcid.clone(), document.clone())
+        let Some(mut document) = self.process_content(fullcid.clone(), Html::parse_document(&html))
+        else {
+            self.metrics.error("no_content");
+            return Err("no_content: the page has no text to index".to_string());
+        };
+        document.result.content_type = content_type;
+        document.result.size = html.len() as u64;
+        document.result.content_cid = content_cid;
+        Ok(Retrieved::Document(Box::new(document)))
     }
 
     /**
      * Determine if the response we've received requires another request from a redirect.
-     * If it requires another request, we will have an updated "full cid" as well.
+     * If it requires another request, we will have an updated "full cid" as well. Fails if the
+     * body of the page redirected to can't be read.
      */
-    fn detect_redirect(&self, url: String, cid: String, document: Html) -> Option<(String, Html)> {
+    fn detect_redirect(
+        &self,
+        cid: String,
+        document: Html,
+    ) -> Result<Option<(String, String)>, String> {
         // ipfs.io does not use normal redirects (301, 307, etc) in the status code, so reqwest client
         // can't detect it. We will have to parse the meta http-equiv tag to get the redirect url.
         let selector = Selector::parse("noscript").unwrap();
         let noscript = document.select(&selector).next();
 
-        if noscript.is_some() {
-            info!("found noscript");
-            let inner_html = noscript.unwrap().inner_html();
+        if let Some(noscript) = noscript {
+            trace!("found noscript");
+            let inner_html = noscript.inner_html();
             if inner_html.find("meta http-equiv=\"refresh\"").is_none() {
-                warn!("no meta http-equiv=\"refresh\" found");
+                debug!("no meta http-equiv=\"refresh\" found");
             } else {
-                info!("found meta http-equiv=\"refresh\"");
+                trace!("found meta http-equiv=\"refresh\"");
                 let start_bytes = inner_html.find("url=").unwrap_or(0);
                 let end_bytes = inner_html[start_bytes..]
-                    .find("\"")
+                    .find('"')
                     .unwrap_or(inner_html.len())
                     + start_bytes;
                 let redirect_url = &inner_html[start_bytes + 4..end_bytes];
-                warn!("Redirecting to {}", redirect_url);
+                info!("Redirecting to {}", redirect_url);
                 // assuming relative
-                let newurl = format!("{}/{}", url, redirect_url);
-
                 let fullcid = cid.clone() + "/" + redirect_url;
-                let client = reqwest::blocking::Client::new();
-                let result = client.get(&newurl).send();
+                let result = self.fetch(&cid, &fullcid);
                 let result = match result {
                     Ok(r) => r,
                     Err(e) => {
-                        warn!("error retrieving content from {}: {}", url, e);
+                        warn!("error retrieving content from {}: {}", fullcid, e);
                         if e.is_timeout() {
-                            self.enqueue(fullcid.clone());
+                            self.enqueue(fullcid.clone(), EnqueuedBy::Redirect(cid.clone()));
                         }
-                        return None;
+                        return Ok(None);
                     }
                 };
                 let response = result;
-                let html = response.text().unwrap();
-                return Some((fullcid, Html::parse_document(html.as_str())));
+                let html = response.text().map_err(|err| {
+                    self.metrics.error(error_class(&err));
+                    format!("body: {}", err)
+                })?;
+                return Ok(Some((fullcid, html)));
             }
         }
-        None
+        Ok(None)
     }
 
     /**
      * Process the content of the page, extract keywords, enqueue more cids, return the IndexResult
+     * along with the positions of the keywords
      */
-    fn process_content(&self, gateway: String, cid: String, document: Html) -> Option<IndexResult> {
+    fn process_content(&self, cid: String, document: Html) -> Option<Document> {
         let fullcid = cid.clone();
 
         let selector = Selector::parse("title").unwrap();
         let titletag = document.select(&selector).next();
         let mut title: String = "".to_string();
-        if titletag.is_some() {
-            title = titletag.unwrap().text().collect();
+        if let Some(titletag) = titletag {
+            title = titletag.text().collect();
         }
 
-        // todo: get all relative links and add them to the index
+        let mut links = Vec::new();
         let selector = Selector::parse("a").unwrap();
         for element in document.select(&selector) {
             let link = element.value().attr("href").unwrap_or("");
-            if link.starts_with(format!("http://{}/ipfs/", gateway).as_str()) {
-                let cid = link[12 + gateway.len()..].to_string();
-                warn!("found link to {}", cid);
-                self.enqueue(cid);
-            } else if link.starts_with(format!("https://{}/ipfs/", gateway).as_str()) {
-                let cid = link[13 + gateway.len()..].to_string();
-                warn!("found link to {}", cid);
-                self.enqueue(cid);
+            let target = if let Some(cid) = self.gateways.ipfs_path(link) {
+                trace!("found link to {}", cid);
+                cid.to_string()
             } else if link.starts_with("http") || link.starts_with("https") {
                 //info!("found link to external url: {}", link);
-            } else if link.starts_with("#") {
+                continue;
+            } else if link.starts_with('#') {
                 // ignore anchors on same page
+                continue;
             } else {
                 // relative link to current top cid
                 //info!("found relative link to {}", link);
                 //let root_cid = fullcid.clone()[0..fullcid.find("/").unwrap_or(fullcid.len())].to_string();
                 //let full_relative = root_cid + "/" + link;
 
-                let last_slash = fullcid.rfind("/").unwrap_or(fullcid.len());
+                let last_slash = fullcid.rfind('/').unwrap_or(fullcid.len());
 
                 if link.is_empty() {
                     // warn!("link is empty, just a link to the same doc, skipping")
-                } else if link.starts_with("../A") {
+                    continue;
+                } else if let Some(rest) = link.strip_prefix("../A") {
                     // handle weird issue where index pages have a ../A/ relative link when they shouldn't
-                    let full_relative =
-                        fullcid.clone()[0..last_slash].to_string() + "/" + &link[4..];
-                    warn!("relative link with cid: {}, link: {}", full_relative, link);
-                    self.enqueue(full_relative);
+                    let full_relative = fullcid.clone()[0..last_slash].to_string() + "/" + rest;
+                    trace!("relative link with cid: {}, link: {}", full_relative, link);
+                    full_relative
                 } else {
                     let full_relative = fullcid.clone()[0..last_slash].to_string() + "/" + link;
-                    warn!("relative link with cid: {}, link: {}", full_relative, link);
-                    self.enqueue(full_relative);
+                    trace!("relative link with cid: {}, link: {}", full_relative, link);
+                    full_relative
                 }
+            };
+            self.enqueue(target.clone(), EnqueuedBy::Link(fullcid.clone()));
+            if target != fullcid {
+                let anchor = element.text().collect::<Vec<_>>().join(" ");
+                links.push(Link {
+                    target,
+                    anchor: anchor.split_whitespace().collect::<Vec<_>>().join(" "),
+                });
             }
         }
-        let selector = Selector::parse("body").unwrap();
-        let body = document.select(&selector).next();
-        let mut index_keywords: HashMap<String, u32> = HashMap::new();
-        if body.is_some() {
-            // collect up the tags in the body, and get the contents within them without their tags
-            let inner = body.unwrap().text().collect::<Vec<_>>();
-            let mut content = inner.join(" ");
-            // this leaves a ton of whitespace between things, so do this next step to remove that
-            let iter = content.split_whitespace();
-            content = iter.fold(String::new(), |a, b| a + b + " ");
-            content = content.trim_start().trim_end().to_string();
-
-            // get the frequency of words and turn it into a btree
-            // https://stackoverflow.com/questions/41220872/how-if-possible-to-sort-a-btreemap-by-value-in-rust
-            let iter = content.split_whitespace();
-            for word in iter {
-                if word.len() > 3 {
-                    let word = word.to_lowercase();
-                    if index_keywords.contains_key(&word) {
-                        let count = index_keywords.get(&word).cloned().unwrap();
-                        index_keywords.insert(word.clone(), count + 1);
-                    } else {
-                        index_keywords.insert(word.clone(), 1);
-                    }
+        // the visible text of the main content, without scripts, styles, navigation and footers
+        let content = content::main_text(&document)?;
+        // get the frequency of words and turn it into a btree
+        // https://stackoverflow.com/questions/41220872/how-if-possible-to-sort-a-btreemap-by-value-in-rust
+        let lang = language::detect(&content);
+        let (index_keywords, positions, words) = self.index_fields(
+            vec![
+                (Field::Body, content.clone()),
+                (Field::Title, title.clone()),
+                (Field::Heading, texts(&document, "h1, h2, h3, h4, h5, h6")),
+                (
+                    Field::Meta,
+                    attributes(
+                        &document,
+                        "meta[name=description], meta[name=keywords]",
+                        "content",
+                    ),
+                ),
+                (Field::Alt, attributes(&document, "img[alt]", "alt")),
+            ],
+            lang.as_deref(),
+        );
+
+        if content.contains("no link named") {
+            warn!("ipfs error on page {}, likely doesn't exist", fullcid);
+        }
 
-                    if self.keywords.contains_key(word.as_str()) {
-                        let keyword_map = self.keywords.get(word.as_str()).unwrap();
-                        keyword_map.insert(cid.clone(), ());
-                        let size = keyword_map.len();
-                        self.keyword_rank.insert(word.clone(), size as u32);
-                    } else {
-                        let keyword_map: DashMap<String, ()> = DashMap::new();
-                        keyword_map.insert(cid.clone(), ());
-                        let size = keyword_map.len();
-                        self.keywords.insert(word.clone(), keyword_map);
-                        self.keyword_rank.insert(word.clone(), size as u32);
+        let excerpt: String = content.chars().take(128).collect();
+        let simhash = simhash::fingerprint(&index_keywords);
+        let mut result = IndexResult::new(fullcid, title, excerpt, index_keywords);
+        result.lang = lang;
+        result.simhash = simhash;
+        result.set_text(&content);
+        Some(Document {
+            result,
+            positions,
+            links,
+            extractor: Extractor::Html,
+            words,
+        })
+    }
+
+    /**
+     * Indexes content that isn't a web page. Text files get their text indexed, anything else
+     * (images, video, archives, ...) can only be found by its file name and type. The size is
+     * that of the whole file, of which only the bytes given are indexed.
+     */
+    fn process_file(&self, cid: String, content_type: String, bytes: &[u8], size: u64) -> Document {
+        // the file name if the item is a path inside a directory
+        let title = match cid.split_once('/') {
+            Some((_, path)) => path.rsplit('/').next().unwrap_or("").to_string(),
+            None => String::new(),
+        };
+        let extractor = if is_text(&content_type) {
+            Extractor::Text
+        } else {
+            Extractor::FileName
+        };
+        let content = if extractor == Extractor::Text {
+            String::from_utf8_lossy(bytes)
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        } else {
+            String::new()
+        };
+        let lang = language::detect(&content);
+        let (index_keywords, positions, words) = self.index_fields(
+            vec![
+                (Field::Body, content.clone()),
+                (Field::Title, title.clone()),
+            ],
+            lang.as_deref(),
+        );
+
+        let excerpt: String = content.chars().take(128).collect();
+        let simhash = simhash::fingerprint(&index_keywords);
+        let mut result = IndexResult::new(cid, title, excerpt, index_keywords);
+        result.lang = lang;
+        result.simhash = simhash;
+        result.set_text(&content);
+        result.content_type = content_type;
+        result.size = size;
+        Document {
+            result,
+            positions,
+            links: Vec::new(),
+            extractor,
+            words,
+        }
+    }
+
+    /**
+     * Counts the keywords of every field and collects their positions, along with the words
+     * stemmed keywords came from. Fields have to be given in order so the positions of every
+     * keyword stay sorted.
+     */
+    fn index_fields(
+        &self,
+        fields: Vec<(Field, String)>,
+        lang: Option<&str>,
+    ) -> (HashMap<String, u32>, Positions, Words) {
+        let mut index_keywords: HashMap<String, u32> = HashMap::new();
+        let mut positions = Positions::new();
+        let mut words = Words::new();
+        let stemmer = self.analyzer.stemmer(lang);
+        for (field, text) in fields {
+            for token in self.analyzer.analyze(&text) {
+                let Some(position) = field.position(token.position) else {
+                    break;
+                };
+                let term = match stemmer {
+                    Some(stemmer) => {
+                        let term = stemmer.stem(&token.term).into_owned();
+                        *words
+                            .entry(term.clone())
+                            .or_default()
+                            .entry(token.term)
+                            .or_insert(0) += 1;
+                        term
                     }
-                }
+                    None => token.term,
+                };
+                *index_keywords.entry(term.clone()).or_insert(0) += 1;
+                positions.entry(term).or_default().push(position);
             }
+        }
+        (index_keywords, positions, words)
+    }
+}
+
+/**
+ * The cid of the content a gateway responded with, which is the same whatever path it was
+ * requested by. Gateways list the cid of every step of the path in X-Ipfs-Roots, the last one is
+ * the content. Failing that, the Etag of immutable content is its cid.
+ */
+fn content_cid(headers: &HeaderMap) -> Option<String> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let candidate = match header("x-ipfs-roots") {
+        Some(roots) => roots.rsplit(',').next().unwrap_or("").trim(),
+        None => {
+            let etag = header("etag")?.trim_start_matches("W/").trim_matches('"');
+            // directory listings get a suffix (cid.dir-index-html)
+            etag.split('.').next().unwrap_or("")
+        }
+    };
+    Cid::try_from(candidate).ok().map(|_| candidate.to_string())
+}
+
+/**
+ * The mime type of a Content-Type header, without parameters like the charset
+ */
+fn mime_type(header: &str) -> String {
+    header.split(';').next().unwrap_or("").trim().to_lowercase()
+}
+
+fn is_html(content_type: &str) -> bool {
+    content_type == "text/html" || content_type == "application/xhtml+xml"
+}
+
+fn is_text(content_type: &str) -> bool {
+    content_type.starts_with("text/")
+        || content_type == "application/json"
+        || content_type == "application/xml"
+        || content_type == "application/javascript"
+}
+
+/**
+ * The text of every element matching the selector, one per line
+ */
+fn texts(document: &Html, selector: &str) -> String {
+    let selector = Selector::parse(selector).unwrap();
+    document
+        .select(&selector)
+        .map(|element| element.text().collect::<Vec<_>>().join(" "))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/**
+ * The value of an attribute of every element matching the selector, one per line
+ */
+fn attributes(document: &Html, selector: &str, attribute: &str) -> String {
+    let selector = Selector::parse(selector).unwrap();
+    document
+        .select(&selector)
+        .filter_map(|element| element.value().attr(attribute))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::click_log::ClickParams;
+    use crate::index_search::{FacetCount, SearchParams};
+
+    fn index_page(queue: &IndexQueue, item: &str, body: &str) {
+        let html = format!(
+            "<html><head><title>{}</title></head><body>{}</body></html>",
+            item, body
+        );
+        let document = queue
+            .process_content(item.to_string(), Html::parse_document(&html))
+            .unwrap();
+        queue.store(item.to_string(), document);
+    }
+
+    #[test]
+    fn search_uses_the_analyzer() {
+        let queue = IndexQueue::new();
+        index_page(&queue, "a", "Renewable energy, solar.");
+        index_page(&queue, "b", "Energy! Wind");
 
-            if content.contains("no link named") {
-                warn!("ipfs error on page {}, likely doesn't exist", fullcid);
+        assert_eq!(
+            queue
+                .search("ENERGY".to_string(), &SearchParams::default())
+                .total,
+            2
+        );
+        assert_eq!(
+            queue
+                .search("energy.".to_string(), &SearchParams::default())
+                .total,
+            2
+        );
+        assert_eq!(
+            queue
+                .search("the solar energy".to_string(), &SearchParams::default())
+                .total,
+            1
+        );
+        assert!(queue
+            .search("solar wind".to_string(), &SearchParams::default())
+            .hits
+            .is_empty());
+        assert!(queue
+            .search("the".to_string(), &SearchParams::default())
+            .hits
+            .is_empty());
+    }
+
+    #[test]
+    fn stemmed_search_with_language_filter() {
+        let queue = IndexQueue::new();
+        index_page(
+            &queue,
+            "en",
+            "The runner was running through the park while the others were walking home",
+        );
+        index_page(
+            &queue,
+            "de",
+            "Der Läufer rannte durch den Park während die anderen nach Hause gingen",
+        );
+
+        assert_eq!(queue.map.get("en").unwrap().lang.as_deref(), Some("eng"));
+        assert_eq!(queue.map.get("de").unwrap().lang.as_deref(), Some("deu"));
+        assert_eq!(
+            queue
+                .search("runs".to_string(), &SearchParams::default())
+                .total,
+            1
+        );
+        assert_eq!(
+            queue
+                .search("walked".to_string(), &SearchParams::default())
+                .total,
+            1
+        );
+        assert_eq!(
+            queue
+                .search("park".to_string(), &SearchParams::default())
+                .total,
+            2
+        );
+
+        let german = SearchParams {
+            lang: Some("deu".to_string()),
+            ..SearchParams::default()
+        };
+        let results = queue.search("park".to_string(), &german);
+        assert_eq!(results.total, 1);
+        assert_eq!(results.hits[0].cid, "de");
+    }
+
+    #[test]
+    fn fields_are_indexed_and_boosted() {
+        let queue = IndexQueue::new();
+        let html = "<html><head><title>Solar power</title>\
+            <meta name=\"description\" content=\"Panels on roofs\"></head>\
+            <body><h2>Batteries</h2><p>Storing energy for the night, solar too</p>\
+            <img src=\"a.png\" alt=\"diagram of an inverter\"></body></html>";
+        let document = queue
+            .process_content("a".to_string(), Html::parse_document(html))
+            .unwrap();
+        queue.store("a".to_string(), document);
+        index_page(&queue, "b", "Solar eclipse and solar wind, solar flares");
+
+        let search = |query: &str| -> Vec<String> {
+            queue
+                .search(query.to_string(), &SearchParams::default())
+                .hits
+                .into_iter()
+                .map(|hit| hit.cid)
+                .collect()
+        };
+        // the title match outweighs the body matches of b
+        assert_eq!(search("solar"), vec!["a", "b"]);
+        assert_eq!(search("title:solar"), vec!["a"]);
+        assert_eq!(search("title:power"), vec!["a"]);
+        assert_eq!(search("heading:batteries"), vec!["a"]);
+        assert_eq!(search("heading:energy"), Vec::<String>::new());
+        assert_eq!(search("meta:roofs"), vec!["a"]);
+        assert_eq!(search("inverter"), vec!["a"]);
+        assert_eq!(search(r#"alt:"diagram of an inverter""#), vec!["a"]);
+        // phrases don't run from one field into the next
+        assert_eq!(search(r#""power batteries""#), Vec::<String>::new());
+    }
+
+    #[test]
+    fn wildcard_and_fuzzy_search() {
+        let queue = IndexQueue::new();
+        index_page(&queue, "a", "Interplanetary gateways");
+        index_page(&queue, "b", "A gateway to the web");
+        index_page(&queue, "c", "Interstellar travel");
+
+        let search = |query: &str| -> Vec<String> {
+            let mut cids: Vec<String> = queue
+                .search(query.to_string(), &SearchParams::default())
+                .hits
+                .into_iter()
+                .map(|hit| hit.cid)
+                .collect();
+            cids.sort();
+            cids
+        };
+        assert_eq!(search("inter*"), vec!["a", "c"]);
+        assert_eq!(search("gat?way*"), vec!["a", "b"]);
+        assert_eq!(search("title:inter*"), Vec::<String>::new());
+        assert_eq!(search("inter* gateway*"), vec!["a"]);
+        assert_eq!(search("intrstellar~"), vec!["c"]);
+        assert_eq!(search("gatewya~1"), vec!["b"]);
+        assert_eq!(search("gatewya~0"), Vec::<String>::new());
+        // too short a prefix would expand to most of the vocabulary
+        assert_eq!(search("*planetary"), Vec::<String>::new());
+        assert_eq!(search("i*"), Vec::<String>::new());
+
+        let results = queue.search("inter*".to_string(), &SearchParams::default());
+        assert!(results.hits[0].snippet.contains("<mark>"));
+        assert_eq!(results.suggestion, None);
+    }
+
+    #[test]
+    fn did_you_mean() {
+        let queue = IndexQueue::new();
+        index_page(&queue, "a", "Interplanetary file system gateways");
+        index_page(&queue, "b", "Interstellar travel");
+
+        let results = queue.search(
+            r#"title:Interplanetray "file sytem""#.to_string(),
+            &SearchParams::default(),
+        );
+        assert_eq!(results.total, 0);
+        assert_eq!(
+            results.suggestion.as_deref(),
+            Some(r#"title:interplanetary "file system""#)
+        );
+        // every word is indexed, the pages just don't have them together
+        let results = queue.search("interstellar file".to_string(), &SearchParams::default());
+        assert_eq!(results.total, 0);
+        assert_eq!(results.suggestion, None);
+        assert_eq!(
+            queue
+                .search("zzzz".to_string(), &SearchParams::default())
+                .suggestion,
+            None
+        );
+
+        // corrections are words, not the stems they are indexed under
+        index_page(
+            &queue,
+            "c",
+            "The public libraries were lending books to the children of the village every afternoon",
+        );
+        let results = queue.search("publc libraryy".to_string(), &SearchParams::default());
+        assert_eq!(results.suggestion.as_deref(), Some("public libraries"));
+    }
+
+    #[test]
+    fn filters_and_facets() {
+        let queue = IndexQueue::new();
+        index_page(&queue, "a", "Solar panels on the roof");
+        let text = "Solar power notes".repeat(1000);
+        let document = queue.process_file(
+            "b/docs/solar.txt".to_string(),
+            "text/plain".to_string(),
+            text.as_bytes(),
+            text.len() as u64,
+        );
+        assert_eq!(document.extractor, Extractor::Text);
+        queue.store("b/docs/solar.txt".to_string(), document);
+        let document = queue.process_file(
+            "c/img/solar-panel.png".to_string(),
+            "image/png".to_string(),
+            &[],
+            4,
+        );
+        assert_eq!(document.extractor, Extractor::FileName);
+        queue.store("c/img/solar-panel.png".to_string(), document);
+
+        let search = |params: SearchParams| -> Vec<String> {
+            let mut cids: Vec<String> = queue
+                .search("solar".to_string(), &params)
+                .hits
+                .into_iter()
+                .map(|hit| hit.cid)
+                .collect();
+            cids.sort();
+            cids
+        };
+        assert_eq!(
+            search(SearchParams::default()),
+            vec!["a", "b/docs/solar.txt", "c/img/solar-panel.png"]
+        );
+        let image = SearchParams {
+            content_type: Some("image".to_string()),
+            ..SearchParams::default()
+        };
+        assert_eq!(search(image), vec!["c/img/solar-panel.png"]);
+        let large = SearchParams {
+            min_size: Some(10_000),
+            ..SearchParams::default()
+        };
+        assert_eq!(search(large), vec!["b/docs/solar.txt"]);
+        let root = SearchParams {
+            root: Some("b".to_string()),
+            indexed_before: Some(u64::MAX),
+            ..SearchParams::default()
+        };
+        assert_eq!(search(root), vec!["b/docs/solar.txt"]);
+        let future = SearchParams {
+            crawled_after: Some(u64::MAX),
+            ..SearchParams::default()
+        };
+        assert!(search(future).is_empty());
+
+        let html = SearchParams {
+            content_type: Some("text/html".to_string()),
+            ..SearchParams::default()
+        };
+        let facets = queue.search("solar".to_string(), &html).facets;
+        assert_eq!(
+            facets.content_type,
+            vec![FacetCount {
+                value: "text/html".to_string(),
+                count: 1
+            }]
+        );
+        let facets = queue
+            .search("solar".to_string(), &SearchParams::default())
+            .facets;
+        assert_eq!(facets.content_type.len(), 3);
+        assert_eq!(
+            facets.size[0],
+            FacetCount {
+                value: "<10KB".to_string(),
+                count: 2
             }
+        );
+        assert_eq!(facets.root.len(), 3);
+    }
 
-            let excerpt_len = if content.len() < 128 {
-                content.len()
-            } else {
-                128
-            };
-            let end = content.char_indices().map(|(i, _)| i).nth(excerpt_len);
-            if end.is_some() {
-                let endunwrap = end.unwrap();
-                let excerpt = content[..endunwrap].to_string();
-                return Some(IndexResult::new(fullcid, title, excerpt, index_keywords));
-            } else {
-                return Some(IndexResult::new(
-                    fullcid,
-                    title,
-                    "".to_string(),
-                    index_keywords,
-                ));
+    #[test]
+    fn reindexing_keeps_first_seen() {
+        let queue = IndexQueue::new();
+        index_page(&queue, "a", "apple");
+        queue.map.get_mut("a").unwrap().first_seen = 1;
+        index_page(&queue, "a", "apple banana");
+        let result = queue.map.get("a").unwrap();
+        assert_eq!(result.first_seen, 1);
+        assert!(result.last_crawled > 1);
+    }
+
+    #[test]
+    fn links_raise_the_rank() {
+        let queue = IndexQueue::new();
+        index_page(&queue, "r/a", "<a href=\"hub\">The  hub</a> solar");
+        index_page(
+            &queue,
+            "r/b",
+            "<a href=\"hub\">Hub</a> <a href=\"#top\">top</a> solar",
+        );
+        index_page(&queue, "r/c", "solar");
+        index_page(&queue, "r/hub", "solar");
+
+        assert_eq!(
+            queue.links.outlinks("r/a"),
+            vec![Link {
+                target: "r/hub".to_string(),
+                anchor: "The hub".to_string()
+            }]
+        );
+        queue.links.update_ranks();
+        let results = queue.search("solar".to_string(), &SearchParams::default());
+        assert_eq!(results.hits[0].cid, "r/hub");
+        assert_eq!(results.hits[0].inlinks, 2);
+
+        queue.remove("r/a");
+        assert_eq!(queue.links.inlink_count("r/hub"), 1);
+    }
+
+    #[test]
+    fn anchor_text_is_indexed() {
+        let queue = IndexQueue::new();
+        let search = |query: &str| -> Vec<String> {
+            queue
+                .search(query.to_string(), &SearchParams::default())
+                .hits
+                .into_iter()
+                .map(|hit| hit.cid)
+                .collect()
+        };
+        // linked before the image is indexed
+        index_page(
+            &queue,
+            "r/a",
+            "<a href=\"sunset.jpg\">Sunset over the harbour</a>",
+        );
+        let image =
+            queue.process_file("r/sunset.jpg".to_string(), "image/jpeg".to_string(), &[], 0);
+        queue.store("r/sunset.jpg".to_string(), image);
+        assert_eq!(search("anchor:harbour"), vec!["r/sunset.jpg"]);
+
+        // linked after
+        index_page(&queue, "r/b", "<a href=\"sunset.jpg\">Evening photo</a>");
+        assert_eq!(search("anchor:evening"), vec!["r/sunset.jpg"]);
+        assert_eq!(search("harbour").len(), 2);
+
+        // the anchors go away with the links
+        index_page(&queue, "r/b", "no more links");
+        queue.remove("r/a");
+        assert!(search("anchor:evening").is_empty());
+        assert!(search("harbour").is_empty());
+        assert!(queue.keywords.get("harbour").is_none());
+        assert!(!queue
+            .map
+            .get("r/sunset.jpg")
+            .unwrap()
+            .keywords
+            .contains_key("evening"));
+        assert_eq!(search("sunset"), vec!["r/sunset.jpg"]);
+    }
+
+    #[test]
+    fn clicks_raise_the_rank() {
+        let queue = IndexQueue::new();
+        index_page(&queue, "a", "Solar panels on the roof");
+        index_page(&queue, "b", "Solar panels in the field");
+        let first = |query: &str| {
+            queue
+                .search(query.to_string(), &SearchParams::default())
+                .hits[0]
+                .cid
+                .clone()
+        };
+        assert_eq!(first("solar"), "a");
+
+        let click = ClickParams {
+            q: Some("Solar".to_string()),
+            cid: "b".to_string(),
+        };
+        assert_eq!(queue.click("x", &click), "https://ipfs.io/ipfs/b");
+        assert_eq!(first("solar"), "b");
+        assert_eq!(first("panels"), "b");
+
+        // clicking again doesn't raise it any further
+        let score = |query: &str| {
+            queue
+                .search(query.to_string(), &SearchParams::default())
+                .hits[0]
+                .score
+        };
+        let before = score("solar");
+        queue.click("x", &click);
+        assert_eq!(score("solar"), before);
+    }
+
+    #[test]
+    fn near_duplicates_are_collapsed() {
+        let queue = IndexQueue::new();
+        let text =
+            "The InterPlanetary File System is a protocol, hypermedia and file sharing peer \
+            to peer network for storing and sharing data in a distributed file system. It uses \
+            content addressing to uniquely identify each file in a global namespace connecting \
+            all computing devices. Files are split into blocks which are linked together in a \
+            Merkle directed acyclic graph, so identical blocks are stored only once and every \
+            change yields a new root hash. Nodes announce the blocks they hold in a distributed \
+            hash table, and other nodes fetch them from whichever peers are closest, verifying \
+            every block against its hash on arrival. Gateways let ordinary web browsers retrieve \
+            this content over plain HTTP requests.";
+        index_page(&queue, "a", text);
+        index_page(&queue, "b", &format!("{} Mirrored by a peer.", text));
+        index_page(
+            &queue,
+            "c",
+            "A file of solar panel measurements, recorded every hour by the inverter on the roof \
+             and uploaded to a shared folder for the neighbours to compare their yields.",
+        );
+
+        let results = queue.search("file".to_string(), &SearchParams::default());
+        assert_eq!(results.total, 2);
+        let counted: usize = results.facets.root.iter().map(|facet| facet.count).sum();
+        assert_eq!(counted, results.total);
+        let copies: Vec<(String, usize)> = results
+            .hits
+            .into_iter()
+            .map(|hit| (hit.cid, hit.similar))
+            .collect();
+        assert!(copies.contains(&("c".to_string(), 0)));
+        assert!(copies
+            .iter()
+            .any(|(cid, similar)| cid != "c" && *similar == 1));
+
+        let all = SearchParams {
+            collapse: Some(false),
+            ..SearchParams::default()
+        };
+        let results = queue.search("file".to_string(), &all);
+        assert_eq!(results.total, 3);
+        assert_eq!(results.facets.content_type[0].count, 3);
+    }
+
+    /**
+     * A queue fetching from a gateway that answers one request with the response, and closes the
+     * connection after it
+     */
+    fn queue_served(response: Vec<u8>) -> IndexQueue {
+        use std::io::Write;
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let gateway = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request);
+            let _ = stream.write_all(&response);
+        });
+        IndexQueue::with_config(&Config {
+            gateways: vec![gateway],
+            data_dir: None,
+            ..Config::default()
+        })
+    }
+
+    #[test]
+    fn truncated_pages_fail_without_a_panic() {
+        // the connection closes long before the promised length
+        let queue = queue_served(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 1000\r\n\r\n<html>"
+                .to_vec(),
+        );
+        match queue.retrieve_content("a".to_string()) {
+            Err(reason) => assert!(reason.starts_with("body: "), "{}", reason),
+            Ok(_) => panic!("a truncated page was indexed"),
+        }
+    }
+
+    #[test]
+    fn only_the_start_of_files_is_downloaded() {
+        // a video is indexed by its name and the size it is said to have, without reading it
+        let queue = queue_served(
+            b"HTTP/1.1 200 OK\r\nContent-Type: video/mp4\r\nContent-Length: 5000000000\r\n\r\n..."
+                .to_vec(),
+        );
+        let Ok(Retrieved::Document(document)) = queue.retrieve_content("a/film.mp4".to_string())
+        else {
+            panic!("the video wasn't indexed");
+        };
+        assert_eq!(document.extractor, Extractor::FileName);
+        assert_eq!(document.result.size, 5_000_000_000);
+
+        // a text file is read up to the limit
+        let text = format!("{}solar", "x ".repeat(MAX_TEXT_BYTES as usize / 2));
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n",
+            text.len()
+        )
+        .into_bytes();
+        response.extend(text.as_bytes());
+        let queue = queue_served(response);
+        let Ok(Retrieved::Document(document)) = queue.retrieve_content("a/notes.txt".to_string())
+        else {
+            panic!("the text wasn't indexed");
+        };
+        assert_eq!(document.extractor, Extractor::Text);
+        assert_eq!(document.result.size, text.len() as u64);
+        assert!(!document.result.keywords.contains_key("solar"));
+    }
+
+    #[test]
+    fn content_cids_from_headers() {
+        let cid = "bafybeiaysi4s6lnjev27ln5icwm6tueaw2vdykrtjkwiphwekaywqhcjze";
+        let file = "QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o";
+        let mut headers = HeaderMap::new();
+        assert_eq!(content_cid(&headers), None);
+        headers.insert(
+            "etag",
+            format!("\"{}.dir-index-html\"", cid).parse().unwrap(),
+        );
+        assert_eq!(content_cid(&headers).as_deref(), Some(cid));
+        headers.insert("x-ipfs-roots", format!("{},{}", cid, file).parse().unwrap());
+        assert_eq!(content_cid(&headers).as_deref(), Some(file));
+        headers.insert("x-ipfs-roots", "not a cid".parse().unwrap());
+        assert_eq!(content_cid(&headers), None);
+    }
+
+    #[test]
+    fn same_content_is_indexed_once() {
+        let queue = IndexQueue::new();
+        let with_content = |item: &str| {
+            let html = "<html><body>Solar panels</body></html>";
+            let mut document = queue
+                .process_content(item.to_string(), Html::parse_document(html))
+                .unwrap();
+            document.result.content_cid = Some("content".to_string());
+            document
+        };
+        queue.store("r/a".to_string(), with_content("r/a"));
+        assert_eq!(queue.indexed_as("content", "r/a"), None);
+        assert_eq!(queue.indexed_as("content", "s/a").as_deref(), Some("r/a"));
+        queue.add_alias("s/a", "r/a");
+        // stored by a worker that fetched it before the first one was stored
+        queue.store("t/a".to_string(), with_content("t/a"));
+
+        assert_eq!(queue.index_length(), 1);
+        assert_eq!(
+            queue.enqueue("t/a".to_string(), EnqueuedBy::Api),
+            EnqueueStatus::AlreadyIndexed
+        );
+        assert_eq!(queue.crawl_status("s/a"), CrawlStatus::Indexed);
+        let results = queue.search("solar".to_string(), &SearchParams::default());
+        assert_eq!(results.total, 1);
+        assert_eq!(results.hits[0].aliases, vec!["s/a", "t/a"]);
+
+        assert!(queue.remove("s/a").is_some());
+        assert_eq!(queue.map.get("r/a").unwrap().aliases, vec!["t/a"]);
+        queue.remove("r/a");
+        assert!(queue.aliases.is_empty());
+        assert!(queue.contents.is_empty());
+    }
+
+    #[test]
+    fn changed_content_frees_the_old_content() {
+        let queue = IndexQueue::new();
+        let with_content = |item: &str, text: &str, content_cid: &str| {
+            let html = format!("<html><body>{}</body></html>", text);
+            let mut document = queue
+                .process_content(item.to_string(), Html::parse_document(&html))
+                .unwrap();
+            document.result.content_cid = Some(content_cid.to_string());
+            document
+        };
+        queue.store("a".to_string(), with_content("a", "Solar panels", "old"));
+        queue.add_alias("b", "a");
+        // stored again with the same content, the alias stays
+        queue.store("a".to_string(), with_content("a", "Solar panels", "old"));
+        assert_eq!(queue.map.get("a").unwrap().aliases, vec!["b"]);
+
+        queue.store("a".to_string(), with_content("a", "Wind turbines", "new"));
+        assert_eq!(queue.indexed_as("old", "c"), None);
+        assert!(queue.map.get("a").unwrap().aliases.is_empty());
+        // the alias is queued to be indexed with the content it has now
+        assert_eq!(queue.crawl_status("b"), CrawlStatus::Queued);
+        assert_eq!(queue.queue.pop().as_deref(), Some("b"));
+
+        queue.store("c".to_string(), with_content("c", "Solar panels", "old"));
+        assert_eq!(queue.index_length(), 2);
+        let results = queue.search("solar".to_string(), &SearchParams::default());
+        assert_eq!(results.hits[0].cid, "c");
+    }
+
+    #[test]
+    fn evicts_to_disk_and_reads_back() {
+        let dir = std::env::temp_dir().join(format!("ipfs_indexer_memory_{}", std::process::id()));
+        let queue = IndexQueue::with_config(&Config {
+            data_dir: Some(dir.clone()),
+            memory_budget: Some(1),
+            ..Config::default()
+        });
+        index_page(&queue, "a", "Solar panels on the roof");
+        index_page(&queue, "b", "Wind turbines on the hill");
+        queue.update_memory();
+
+        let usage = queue.memory.usage();
+        assert!(queue.keywords.is_empty());
+        assert_eq!((usage.postings, usage.bodies), (0, 0));
+        assert_eq!(usage.evicted_bodies, 2);
+        assert!(usage.evicted_keywords > 0);
+        assert!(usage.disk > 0);
+
+        let results = queue.search("panels".to_string(), &SearchParams::default());
+        assert_eq!(results.total, 1);
+        assert_eq!(
+            results.hits[0].snippet,
+            "Solar <mark>panels</mark> on the roof"
+        );
+
+        // updates read the evicted postings back in before changing them
+        index_page(&queue, "c", "Rooftop solar panels");
+        queue.remove("a");
+        let results = queue.search("panels".to_string(), &SearchParams::default());
+        assert_eq!(results.total, 1);
+        assert_eq!(results.hits[0].cid, "c");
+        assert_eq!(
+            queue
+                .search("hill".to_string(), &SearchParams::default())
+                .total,
+            1
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn new_anchors_leave_evicted_postings_on_disk() {
+        let dir = std::env::temp_dir().join(format!("ipfs_indexer_anchors_{}", std::process::id()));
+        let queue = IndexQueue::with_config(&Config {
+            data_dir: Some(dir.clone()),
+            memory_budget: Some(1),
+            ..Config::default()
+        });
+        index_page(&queue, "r/b", "Wind turbines on the hill");
+        queue.update_memory();
+        assert!(queue.keywords.is_empty());
+
+        index_page(&queue, "r/a", "<a href=\"b\">Renewable energy</a>");
+        index_page(&queue, "r/a", "<a href=\"b\">Clean power</a>");
+        assert!(queue.keywords.get("turbin").is_none());
+        assert!(queue.keywords.get("hill").is_none());
+        let results = queue.search("anchor:clean".to_string(), &SearchParams::default());
+        assert_eq!(results.hits[0].cid, "r/b");
+        assert_eq!(
+            queue
+                .search("anchor:renewable".to_string(), &SearchParams::default())
+                .total,
+            0
+        );
+        assert_eq!(
+            queue
+                .search("turbines".to_string(), &SearchParams::default())
+                .total,
+            1
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn measures_memory_without_a_budget() {
+        let queue = IndexQueue::new();
+        index_page(&queue, "a", "Solar panels on the roof");
+        // nothing measures it in the background, the status report does
+        assert!(!queue.memory.is_limited());
+        let measured = queue.status_report().memory.index.keywords;
+        assert!(measured > 0);
+        // and then reports the same measurement for a while
+        index_page(&queue, "b", "Wind turbines on the hill");
+        assert_eq!(queue.status_report().memory.index.keywords, measured);
+        queue.update_memory();
+        let usage = queue.memory.usage();
+        assert!(!queue.keywords.is_empty());
+        assert!(usage.postings > 0 && usage.bodies > 0 && usage.results > 0);
+        assert_eq!((usage.budget, usage.disk), (None, 0));
+    }
+
+    #[test]
+    fn search_results_have_snippets() {
+        let queue = IndexQueue::new();
+        let filler = "Navigation links and other text before the content. ".repeat(10);
+        index_page(
+            &queue,
+            "a",
+            &format!("{}Solar panels turn sunlight into power.", filler),
+        );
+        index_page(&queue, "b", "Solar <b>wind</b>");
+        index_page(&queue, "c", "Solar eclipse");
+
+        let results = queue.search("solar panel".to_string(), &SearchParams::default());
+        assert_eq!(results.total, 1);
+        let snippet = &results.hits[0].snippet;
+        assert!(snippet.starts_with("… "));
+        assert!(snippet.contains("<mark>Solar</mark> <mark>panels</mark> turn"));
+
+        let page = SearchParams {
+            limit: Some(1),
+            offset: Some(1),
+            ..SearchParams::default()
+        };
+        let results = queue.search("solar".to_string(), &page);
+        assert_eq!(results.total, 3);
+        assert_eq!(results.hits.len(), 1);
+    }
+
+    #[test]
+    fn search_chinese_and_japanese() {
+        let queue = IndexQueue::new();
+        index_page(&queue, "zh", "星际文件系统是一种点对点的分布式文件系统");
+        index_page(&queue, "ja", "東京都は日本の首都です");
+
+        assert_eq!(
+            queue
+                .search("文件系统".to_string(), &SearchParams::default())
+                .total,
+            1
+        );
+        assert_eq!(
+            queue
+                .search("分布式".to_string(), &SearchParams::default())
+                .total,
+            1
+        );
+        assert_eq!(
+            queue
+                .search("東京".to_string(), &SearchParams::default())
+                .total,
+            1
+        );
+        assert_eq!(
+            queue
+                .search("首都".to_string(), &SearchParams::default())
+                .hits[0]
+                .cid,
+            "ja"
+        );
+        assert!(queue
+            .search("京東".to_string(), &SearchParams::default())
+            .hits
+            .is_empty());
+    }
+
+    #[test]
+    fn phrase_and_proximity_search() {
+        let queue = IndexQueue::new();
+        index_page(&queue, "a", "The cat in the hat sat on a mat");
+        index_page(&queue, "b", "The hat was on the cat");
+        index_page(
+            &queue,
+            "c",
+            "A cat with a big hat, a mat, some other things and then a hat",
+        );
+
+        let search = |query: &str| -> Vec<String> {
+            queue
+                .search(query.to_string(), &SearchParams::default())
+                .hits
+                .into_iter()
+                .map(|result| result.cid)
+                .collect()
+        };
+        assert_eq!(search(r#""cat in the hat""#), vec!["a"]);
+        assert_eq!(search(r#""hat cat""#), Vec::<String>::new());
+        let mut near = search(r#""hat cat"~3"#);
+        near.sort();
+        assert_eq!(near, vec!["a", "b", "c"]);
+        assert_eq!(search(r#""hat mat"~1"#), vec!["c"]);
+
+        // closer terms rank higher
+        index_page(&queue, "d", "cat, lots of words, dog");
+        index_page(&queue, "e", "cat dog");
+        assert_eq!(search("cat dog"), vec!["e", "d"]);
+    }
+
+    #[test]
+    fn reindex_replaces_keywords() {
+        let queue = IndexQueue::new();
+        index_page(&queue, "a", "apple banana");
+        index_page(&queue, "b", "banana");
+        index_page(&queue, "a", "cherry banana");
+
+        assert!(queue
+            .search("apple".to_string(), &SearchParams::default())
+            .hits
+            .is_empty());
+        assert_eq!(
+            queue
+                .search("cherry".to_string(), &SearchParams::default())
+                .total,
+            1
+        );
+        assert_eq!(
+            queue
+                .search("banana".to_string(), &SearchParams::default())
+                .total,
+            2
+        );
+        assert_eq!(queue.keyword_rank.get("apple"), None);
+        assert_eq!(queue.keyword_rank.get("banana"), Some(2));
+    }
+
+    #[test]
+    fn remove_cleans_up_keywords() {
+        let queue = IndexQueue::new();
+        index_page(&queue, "a", "apple banana");
+        index_page(&queue, "b", "banana");
+
+        assert!(queue.remove("a").is_some());
+        assert!(queue.remove("a").is_none());
+        assert_eq!(queue.index_length(), 1);
+        assert!(!queue.keywords.contains_key("apple"));
+        assert_eq!(queue.keyword_rank.get("apple"), None);
+        assert_eq!(queue.keyword_rank.get("banana"), Some(1));
+        assert_eq!(
+            queue
+                .search("banana".to_string(), &SearchParams::default())
+                .total,
+            1
+        );
+    }
+
+    #[test]
+    fn removed_items_stay_removed() {
+        let queue = IndexQueue::new();
+        let page = |item: &str| {
+            queue
+                .process_content(
+                    item.to_string(),
+                    Html::parse_document("<html><body>Solar panels</body></html>"),
+                )
+                .unwrap()
+        };
+        queue.store("a".to_string(), page("a"));
+        queue.enqueue("b".to_string(), EnqueuedBy::Api);
+        // a worker was already fetching a when it was removed
+        let fetched = page("a");
+        assert!(queue.remove("a").is_some());
+        queue.remove("b");
+        queue.store("a".to_string(), fetched);
+        assert_eq!(queue.index_length(), 0);
+        assert_eq!(queue.crawl_status("b"), CrawlStatus::NotIndexed);
+        assert_eq!(
+            queue.enqueue("a".to_string(), EnqueuedBy::Link("c".to_string())),
+            EnqueueStatus::Removed
+        );
+
+        assert_eq!(queue.reindex("a".to_string()), EnqueueStatus::Queued);
+        queue.store("a".to_string(), page("a"));
+        assert_eq!(queue.index_length(), 1);
+
+        // removing an item the index doesn't know about doesn't keep it out
+        assert!(queue.remove("d").is_none());
+        assert!(queue.removed.get("d").is_none());
+        assert_eq!(
+            queue.enqueue("d".to_string(), EnqueuedBy::Api),
+            EnqueueStatus::Queued
+        );
+        // but one a worker is fetching is kept out
+        let fetching = queue.metrics.working_on("e");
+        assert!(queue.remove("e").is_none());
+        queue.store("e".to_string(), page("e"));
+        drop(fetching);
+        assert_eq!(queue.crawl_status("e"), CrawlStatus::NotIndexed);
+        assert_eq!(queue.index_length(), 1);
+    }
+
+    #[test]
+    fn concurrent_updates_keep_rank_consistent() {
+        let queue = std::sync::Arc::new(IndexQueue::new());
+        let workers: Vec<_> = (0..8)
+            .map(|worker| {
+                let queue = queue.clone();
+                std::thread::spawn(move || {
+                    for round in 0..20 {
+                        let item = format!("item{}", (worker + round) % 5);
+                        index_page(
+                            &queue,
+                            &item,
+                            &format!("shared word{} round{}", worker, round),
+                        );
+                        if round % 3 == 0 {
+                            queue.unindex(&item);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        assert_eq!(queue.keywords.len(), queue.keyword_rank.len());
+        for entry in queue.keywords.iter() {
+            assert_eq!(
+                queue.keyword_rank.get(entry.key()),
+                Some(entry.len() as u32)
+            );
+            for cid in entry.value().keys() {
+                assert!(queue
+                    .map
+                    .get(cid)
+                    .unwrap()
+                    .keywords
+                    .contains_key(entry.key()));
             }
         }
-        None
+        // every document contains the shared word
+        assert_eq!(queue.top_keywords(1)[0].1, queue.index_length() as u32);
     }
 }
@@ -1,4 +1,10 @@
-use std::{collections::HashMap, fmt};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{Read, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 #[derive(Clone)]
 pub struct IndexResult {
@@ -6,6 +12,17 @@ pub struct IndexResult {
     pub title: String,
     pub excerpt: String,
     pub keywords: HashMap<String, u32>, // maps keyword to occurrence count
+    pub lang: Option<String>,           // ISO 639-3 code of the detected language
+    pub body: Vec<u8>,                  // deflated document text, for snippets
+    pub content_type: String,           // mime type without parameters (text/html)
+    pub size: u64,                      // bytes of content retrieved
+    pub root: String,                   // the cid without the path
+    pub first_seen: u64,                // unix time the item was first indexed
+    pub last_crawled: u64,              // unix time the item was last fetched
+    pub simhash: Option<u64>,           // fingerprint of the keywords, to find near duplicates
+    pub content_cid: Option<String>,    // cid of the content itself, the same under every path
+    pub aliases: Vec<String>,           // other items found with the same content
+    pub anchor_keywords: HashMap<String, u32>, // the part of keywords from links to the item
 }
 
 impl IndexResult {
@@ -15,12 +32,50 @@ impl IndexResult {
         excerpt: String,
         keywords: HashMap<String, u32>,
     ) -> IndexResult {
+        let now = now();
         IndexResult {
-            cid: cid,
-            title: title,
-            excerpt: excerpt,
-            keywords: keywords,
+            root: cid.split('/').next().unwrap_or("").to_string(),
+            cid,
+            title,
+            excerpt,
+            keywords,
+            lang: None,
+            body: Vec::new(),
+            content_type: "text/html".to_string(),
+            size: 0,
+            first_seen: now,
+            last_crawled: now,
+            simhash: None,
+            content_cid: None,
+            aliases: Vec::new(),
+            anchor_keywords: HashMap::new(),
+        }
+    }
+
+    /**
+     * Stores the document text compressed, it's only needed to build snippets for search results
+     */
+    pub fn set_text(&mut self, text: &str) {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+        // writing to a Vec can't fail
+        if encoder.write_all(text.as_bytes()).is_ok() {
+            self.body = encoder.finish().unwrap_or_default();
+        }
+    }
+
+    pub fn text(&self) -> String {
+        IndexResult::inflate(&self.body)
+    }
+
+    /**
+     * Decompresses text stored by set_text
+     */
+    pub fn inflate(body: &[u8]) -> String {
+        let mut text = String::new();
+        if DeflateDecoder::new(body).read_to_string(&mut text).is_err() {
+            text.clear();
         }
+        text
     }
 
     /**
@@ -34,13 +89,22 @@ impl IndexResult {
     }
 }
 
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
 impl fmt::Display for IndexResult {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "CID: {}\nTitle: {}\nExcerpt:\n{}\nKeywords:{:?}",
+            "CID: {}\nTitle: {}\nType: {} ({} bytes)\nLanguage: {}\nExcerpt:\n{}\nKeywords:{:?}",
             self.cid,
             self.title,
+            self.content_type,
+            self.size,
+            self.lang.as_deref().unwrap_or("unknown"),
             self.excerpt,
             self.top_n_keywords(10)
         )
@@ -51,9 +115,12 @@ impl fmt::Debug for IndexResult {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "CID: {}\nTitle: {}\nExcerpt:{}\nKeywords:{:?}",
+            "CID: {}\nTitle: {}\nType: {} ({} bytes)\nLanguage: {}\nExcerpt:{}\nKeywords:{:?}",
             self.cid,
             self.title,
+            self.content_type,
+            self.size,
+            self.lang.as_deref().unwrap_or("unknown"),
             self.excerpt,
             self.top_n_keywords(10)
         )
@@ -108,4 +175,19 @@ mod tests {
         );
         assert_eq!(result.top_n_keywords(2).len(), 2);
     }
+
+    #[test]
+    fn compressed_text() {
+        let mut result = IndexResult::new(
+            "1".to_string(),
+            "title".to_string(),
+            "excerpt".to_string(),
+            HashMap::new(),
+        );
+        assert_eq!(result.text(), "");
+        let text = "the interplanetary file system ".repeat(50);
+        result.set_text(&text);
+        assert!(result.body.len() < text.len() / 4);
+        assert_eq!(result.text(), text);
+    }
 }
@@ -0,0 +1,708 @@
+use crate::analyzer::normalize;
+use crate::field::{Boosts, Field};
+use crate::index_queue::IndexQueue;
+use crate::index_result::IndexResult;
+use crate::pattern;
+use crate::query::{Clause, Phrase, Query};
+use crate::simhash;
+use crate::snippet::snippet;
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+// cid -> sorted positions of a term in that document
+type Postings = HashMap<String, Vec<u32>>;
+
+const DEFAULT_LIMIT: usize = 20;
+// most index terms a wildcard or fuzzy term is expanded to
+const MAX_EXPANSIONS: usize = 50;
+// characters a wildcard pattern has to start with
+const MIN_WILDCARD_PREFIX: usize = 2;
+// values listed per facet
+const MAX_FACET_VALUES: usize = 10;
+
+/**
+ * Options that narrow down a search
+ */
+#[derive(Default, Deserialize)]
+pub struct SearchParams {
+    // only return documents in this language (ISO 639-3 code)
+    pub lang: Option<String>,
+    // only documents of this mime type (text/plain), or of this kind (image)
+    #[serde(rename = "type")]
+    pub content_type: Option<String>,
+    // size in bytes
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    // only documents under this root cid
+    pub root: Option<String>,
+    // unix timestamps of when documents were first indexed and last crawled
+    pub indexed_after: Option<u64>,
+    pub indexed_before: Option<u64>,
+    pub crawled_after: Option<u64>,
+    pub crawled_before: Option<u64>,
+    // false to list near duplicate copies as hits of their own
+    pub collapse: Option<bool>,
+    // page of results to return
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+impl SearchParams {
+    fn accepts(&self, result: &IndexResult) -> bool {
+        let within = |value: u64, min: Option<u64>, max: Option<u64>| {
+            min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+        };
+        self.lang
+            .as_ref()
+            .is_none_or(|lang| result.lang.as_ref() == Some(lang))
+            && self
+                .content_type
+                .as_ref()
+                .is_none_or(|content_type| matches_type(&result.content_type, content_type))
+            && self.root.as_ref().is_none_or(|root| result.root == *root)
+            && within(result.size, self.min_size, self.max_size)
+            && within(result.first_seen, self.indexed_after, self.indexed_before)
+            && within(result.last_crawled, self.crawled_after, self.crawled_before)
+    }
+}
+
+/**
+ * A type filter with a slash has to match exactly, without one it matches the top level type
+ * (image matches image/png)
+ */
+fn matches_type(content_type: &str, filter: &str) -> bool {
+    let filter = filter.to_lowercase();
+    if filter.contains('/') {
+        content_type == filter
+    } else {
+        content_type.split('/').next() == Some(filter.as_str())
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FacetCount {
+    pub value: String,
+    pub count: usize,
+}
+
+/**
+ * Number of matching documents with each value of the filterable attributes, the most common
+ * values first
+ */
+#[derive(Debug, Default, Serialize)]
+pub struct Facets {
+    pub content_type: Vec<FacetCount>,
+    pub lang: Vec<FacetCount>,
+    pub size: Vec<FacetCount>,
+    pub root: Vec<FacetCount>,
+}
+
+#[derive(Default)]
+struct FacetCounter {
+    content_type: HashMap<String, usize>,
+    lang: HashMap<String, usize>,
+    size: HashMap<String, usize>,
+    root: HashMap<String, usize>,
+}
+
+impl FacetCounter {
+    fn add(&mut self, result: &IndexResult) {
+        *self
+            .content_type
+            .entry(result.content_type.clone())
+            .or_default() += 1;
+        let lang = result.lang.as_deref().unwrap_or("unknown");
+        *self.lang.entry(lang.to_string()).or_default() += 1;
+        *self
+            .size
+            .entry(size_bucket(result.size).to_string())
+            .or_default() += 1;
+        *self.root.entry(result.root.clone()).or_default() += 1;
+    }
+
+    fn facets(self) -> Facets {
+        let top = |counts: HashMap<String, usize>| -> Vec<FacetCount> {
+            let mut counts: Vec<FacetCount> = counts
+                .into_iter()
+                .map(|(value, count)| FacetCount { value, count })
+                .collect();
+            counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+            counts.truncate(MAX_FACET_VALUES);
+            counts
+        };
+        Facets {
+            content_type: top(self.content_type),
+            lang: top(self.lang),
+            size: top(self.size),
+            root: top(self.root),
+        }
+    }
+}
+
+fn size_bucket(size: u64) -> &'static str {
+    match size {
+        0..10_000 => "<10KB",
+        10_000..100_000 => "10KB-100KB",
+        100_000..1_000_000 => "100KB-1MB",
+        1_000_000..10_000_000 => "1MB-10MB",
+        _ => ">10MB",
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    pub cid: String,
+    pub title: String,
+    // html escaped text around the matches, with the matching words in <mark></mark>
+    pub snippet: String,
+    pub lang: Option<String>,
+    pub score: f64,
+    // number of indexed items linking to this one
+    pub inlinks: usize,
+    // near duplicate copies of this document that matched too, left out of the hits
+    pub similar: usize,
+    // other items with exactly the same content
+    pub aliases: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResults {
+    pub query: String,
+    // number of matching documents, not just the ones in hits, counting near duplicates once
+    pub total: usize,
+    pub hits: Vec<SearchHit>,
+    // counts over every matching document, not just the hits, counting near duplicates once
+    pub facets: Facets,
+    // the query with misspelled words replaced, when nothing matched
+    pub suggestion: Option<String>,
+}
+
+impl IndexQueue {
+    /**
+     * Returns the results matching every clause of the query and the filters in the params, best
+     * matches first, along with facet counts over all of them. Like the total, the facets count
+     * near duplicates once.
+     *
+     * Documents are scored by tf-idf over the query terms, with every occurrence weighted by the
+     * boost of the field it is in, and the score goes up (at most doubling) the closer together
+     * the terms appear in the document. It is then raised for documents with a high PageRank and
+     * for the ones people clicked on, especially when searching for the same thing. Only the
+     * requested page of results gets a snippet, since that means decompressing the document
+     * text. Near duplicates of a better match are left out, the match says how many copies it
+     * has. When nothing matches, the results suggest the query with misspelled words corrected.
+     */
+    pub fn search(&self, query: String, params: &SearchParams) -> SearchResults {
+        let start = Instant::now();
+        // for the search, we could iterate through all of the indexed results and then search
+        // each result for a keyword, or instead we could do a keyword lookup in the keyword map
+        // which will give us a list of CIDs that contain the keyword.
+
+        let lang = params.lang.as_deref();
+        let parsed = Query::parse(&self.analyzer, &query);
+
+        // the postings of every distinct token, wildcard and fuzzy term in the query
+        let mut postings: HashMap<String, Postings> = HashMap::new();
+        // the index terms the wildcard and fuzzy terms matched, to highlight in the snippets
+        let mut expansions: HashSet<String> = HashSet::new();
+        for clause in &parsed.clauses {
+            let tokens: Vec<&String> = match clause {
+                Clause::Term(term, _) => vec![term],
+                Clause::Phrase(phrase, _) => phrase.tokens.iter().map(|t| &t.term).collect(),
+                Clause::Wildcard(..) | Clause::Fuzzy(..) => {
+                    let terms = self.expand(clause, lang);
+                    postings.insert(clause_key(clause), self.exact_postings(&terms));
+                    expansions.extend(terms);
+                    continue;
+                }
+            };
+            for token in tokens {
+                if !postings.contains_key(token) {
+                    postings.insert(token.clone(), self.term_postings(token, lang));
+                }
+            }
+        }
+
+        let mut cids: Option<HashSet<String>> = None;
+        for clause in &parsed.clauses {
+            let clause_cids = match clause {
+                Clause::Phrase(phrase, field) => phrase_cids(phrase, *field, &postings),
+                Clause::Term(_, field)
+                | Clause::Wildcard(_, field)
+                | Clause::Fuzzy(_, _, field) => postings[&clause_key(clause)]
+                    .iter()
+                    .filter(|(_, positions)| in_field(positions, *field).next().is_some())
+                    .map(|(cid, _)| cid.clone())
+                    .collect(),
+            };
+            cids = Some(match cids {
+                Some(cids) => cids.intersection(&clause_cids).cloned().collect(),
+                None => clause_cids,
+            });
+        }
+
+        let total = self.index_length().max(1) as f64;
+        let mut scored: Vec<(f64, String)> = Vec::new();
+        for cid in cids.unwrap_or_default() {
+            let Some(index_result) = self.map.get(&cid) else {
+                continue;
+            };
+            if !params.accepts(&index_result) {
+                continue;
+            }
+            let score = score(&cid, &postings, total, &self.boosts)
+                * self.links.authority(&cid)
+                * self.clicks.popularity(&query, &cid);
+            scored.push((score, cid));
+        }
+        scored.sort_by(|a, b| {
+            b.0.partial_cmp(&a.0)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a.1.cmp(&b.1))
+        });
+
+        let scored = if params.collapse.unwrap_or(true) {
+            self.collapse(scored)
+        } else {
+            scored
+                .into_iter()
+                .map(|(score, cid)| (score, cid, 0))
+                .collect()
+        };
+        let mut facets = FacetCounter::default();
+        for (_, cid, _) in &scored {
+            if let Some(index_result) = self.map.get(cid) {
+                facets.add(&index_result);
+            }
+        }
+
+        let suggestion = if scored.is_empty() {
+            self.did_you_mean(&query, &parsed, &postings)
+        } else {
+            None
+        };
+
+        // every form of the query tokens that could be in the index, to find them in the text
+        let mut terms: HashSet<String> = postings
+            .keys()
+            .filter(|key| !key.contains(['*', '?', '~']))
+            .flat_map(|token| self.analyzer.query_variants(token, lang))
+            .collect();
+        terms.extend(expansions);
+        let total = scored.len();
+        // count a search once, not for every page of its results
+        if total > 0 && params.offset.unwrap_or(0) == 0 {
+            self.query_log.record(&query, total);
+        }
+        let hits = scored
+            .into_iter()
+            .skip(params.offset.unwrap_or(0))
+            .take(params.limit.unwrap_or(DEFAULT_LIMIT))
+            .filter_map(|(score, cid, similar)| {
+                let index_result = self.map.get(&cid)?;
+                let text = self.document_text(&cid, &index_result);
+                let doc_lang = index_result.lang.as_deref();
+                let inlinks = self.links.inlink_count(&cid);
+                Some(SearchHit {
+                    snippet: snippet(&self.analyzer, &text, doc_lang, &terms),
+                    cid,
+                    title: index_result.title.clone(),
+                    lang: index_result.lang.clone(),
+                    score,
+                    inlinks,
+                    similar,
+                    aliases: index_result.aliases.clone(),
+                })
+            })
+            .collect();
+        self.metrics.searched(start.elapsed());
+        SearchResults {
+            query,
+            total,
+            hits,
+            facets: facets.facets(),
+            suggestion,
+        }
+    }
+
+    /**
+     * Folds every result into the best scoring near duplicate before it, the results are given
+     * best first. Returns the remaining results with the number of copies folded into each.
+     */
+    fn collapse(&self, scored: Vec<(f64, String)>) -> Vec<(f64, String, usize)> {
+        let mut kept: Vec<(f64, String, usize)> = Vec::new();
+        let mut fingerprints: Vec<u64> = Vec::new();
+        // band of a fingerprint -> the kept results with that band
+        let mut bands: HashMap<(usize, u16), Vec<usize>> = HashMap::new();
+        for (score, cid) in scored {
+            let fingerprint = self.map.get(&cid).and_then(|result| result.simhash);
+            if let Some(fingerprint) = fingerprint {
+                let original = simhash::bands(fingerprint)
+                    .iter()
+                    .filter_map(|band| bands.get(band))
+                    .flatten()
+                    .find(|i| simhash::is_near_duplicate(fingerprints[**i], fingerprint))
+                    .copied();
+                if let Some(original) = original {
+                    kept[original].2 += 1;
+                    continue;
+                }
+                for band in simhash::bands(fingerprint) {
+                    bands.entry(band).or_default().push(kept.len());
+                }
+            }
+            // documents without a fingerprint never match, their slot is never looked at
+            fingerprints.push(fingerprint.unwrap_or_default());
+            kept.push((score, cid, 0));
+        }
+        kept
+    }
+
+    /**
+     * The positions of a query token in every document containing it. Documents are indexed
+     * under the stem for their language, so every form the token could have been stored as is
+     * looked up.
+     */
+    fn term_postings(&self, token: &str, lang: Option<&str>) -> Postings {
+        self.exact_postings(&self.analyzer.query_variants(token, lang))
+    }
+
+    /**
+     * The indexed terms matching a wildcard or fuzzy clause, the most common first. A fuzzy term
+     * is matched in its stemmed forms too, and the first letter has to be right so that only part
+     * of the dictionary has to be compared with it.
+     */
+    fn expand(&self, clause: &Clause, lang: Option<&str>) -> Vec<String> {
+        match clause {
+            Clause::Wildcard(pattern, _) => {
+                let prefix = pattern::literal_prefix(pattern);
+                // a pattern starting with a wildcard would go through the whole vocabulary
+                if prefix.chars().count() < MIN_WILDCARD_PREFIX {
+                    return Vec::new();
+                }
+                self.keyword_rank
+                    .matching(
+                        prefix,
+                        |term| pattern::wildcard_matches(pattern, term),
+                        MAX_EXPANSIONS,
+                    )
+                    .into_iter()
+                    .map(|(term, _)| term)
+                    .collect()
+            }
+            Clause::Fuzzy(term, distance, _) => {
+                let mut terms: Vec<String> = Vec::new();
+                for variant in self.analyzer.query_variants(term, lang) {
+                    for (found, _) in self.fuzzy_matches(&variant, *distance) {
+                        if !terms.contains(&found) {
+                            terms.push(found);
+                        }
+                    }
+                }
+                terms
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /**
+     * Indexed terms within distance edits of the term with their document frequency, the most
+     * common first
+     */
+    fn fuzzy_matches(&self, term: &str, distance: u32) -> Vec<(String, u32)> {
+        let Some(first) = term.chars().next() else {
+            return Vec::new();
+        };
+        self.keyword_rank.matching(
+            first.encode_utf8(&mut [0; 4]),
+            |found| pattern::edit_distance(term, found, distance).is_some(),
+            MAX_EXPANSIONS,
+        )
+    }
+
+    /**
+     * Rewrites the query with every word that isn't in the index replaced by the closest indexed
+     * term (fewest typos, then most documents). None if there is nothing to correct.
+     */
+    fn did_you_mean(
+        &self,
+        query: &str,
+        parsed: &Query,
+        postings: &HashMap<String, Postings>,
+    ) -> Option<String> {
+        let mut corrections: HashMap<String, String> = HashMap::new();
+        for clause in &parsed.clauses {
+            let tokens: Vec<&String> = match clause {
+                Clause::Term(term, _) => vec![term],
+                Clause::Phrase(phrase, _) => phrase.tokens.iter().map(|t| &t.term).collect(),
+                _ => continue,
+            };
+            for token in tokens {
+                if !postings[token].is_empty() || corrections.contains_key(token) {
+                    continue;
+                }
+                let best = self
+                    .fuzzy_matches(token, pattern::max_edits(token))
+                    .into_iter()
+                    .filter_map(|(found, count)| {
+                        let distance = pattern::edit_distance(token, &found, 2)?;
+                        Some((distance, Reverse(count), found))
+                    })
+                    .min();
+                // the closest term may be a stem, people are shown a word
+                if let Some((_, _, found)) = best {
+                    corrections.insert(token.clone(), self.word_forms.word(&found));
+                }
+            }
+        }
+        if corrections.is_empty() {
+            return None;
+        }
+        Some(replace_words(query, &corrections))
+    }
+
+    /**
+     * The positions of the terms (exactly as indexed) in every document containing any of them
+     */
+    fn exact_postings(&self, terms: &[String]) -> Postings {
+        let mut postings = Postings::new();
+        for term in terms {
+            // copy the postings out so the keywords guard is released before looking into map
+            if let Some(found) = self.keyword_postings(term) {
+                for (cid, positions) in found.iter() {
+                    postings
+                        .entry(cid.clone())
+                        .or_default()
+                        .extend(positions.iter().cloned());
+                }
+            }
+        }
+        for positions in postings.values_mut() {
+            positions.sort_unstable();
+            positions.dedup();
+        }
+        postings
+    }
+}
+
+/**
+ * What a term, wildcard or fuzzy clause's postings are stored under. Terms never contain the
+ * punctuation, so the keys can't clash.
+ */
+fn clause_key(clause: &Clause) -> String {
+    match clause {
+        Clause::Term(term, _) => term.clone(),
+        Clause::Wildcard(pattern, _) => pattern.clone(),
+        Clause::Fuzzy(term, distance, _) => format!("{}~{}", term, distance),
+        Clause::Phrase(..) => String::new(),
+    }
+}
+
+/**
+ * Replaces the words of the text that normalize to one of the corrections, keeping everything
+ * else (quotes, field names, punctuation) as it is
+ */
+fn replace_words(text: &str, corrections: &HashMap<String, String>) -> String {
+    let mut replaced = String::new();
+    let mut word = String::new();
+    for c in text.chars().chain(std::iter::once(' ')) {
+        if c.is_alphanumeric() {
+            word.push(c);
+            continue;
+        }
+        if !word.is_empty() {
+            match corrections.get(&normalize(&word)) {
+                Some(correction) => replaced.push_str(correction),
+                None => replaced.push_str(&word),
+            }
+            word.clear();
+        }
+        replaced.push(c);
+    }
+    replaced.pop();
+    replaced
+}
+
+fn phrase_cids(
+    phrase: &Phrase,
+    field: Option<Field>,
+    postings: &HashMap<String, Postings>,
+) -> HashSet<String> {
+    let first = &postings[&phrase.tokens[0].term];
+    first
+        .keys()
+        .filter(|cid| {
+            let lists: Option<Vec<Vec<u32>>> = phrase
+                .tokens
+                .iter()
+                .map(|token| {
+                    let positions = postings[&token.term].get(*cid)?;
+                    Some(in_field(positions, field).collect())
+                })
+                .collect();
+            match lists {
+                Some(lists) => phrase_matches(phrase, &lists.iter().collect::<Vec<_>>()),
+                None => false,
+            }
+        })
+        .cloned()
+        .collect()
+}
+
+/**
+ * The positions that are in the field, or all of them without one
+ */
+fn in_field(positions: &[u32], field: Option<Field>) -> impl Iterator<Item = u32> + '_ {
+    positions
+        .iter()
+        .cloned()
+        .filter(move |position| field.is_none_or(|field| Field::of(*position) == field))
+}
+
+/**
+ * Checks if the positions (one list per phrase token) contain the phrase. Without slop every
+ * token has to be at the same offset from the first token as in the phrase, with slop the tokens
+ * only have to be within the length of the phrase plus slop words of each other.
+ */
+fn phrase_matches(phrase: &Phrase, lists: &[&Vec<u32>]) -> bool {
+    if phrase.slop == 0 {
+        return lists[0].iter().any(|start| {
+            phrase.tokens.iter().zip(lists).all(|(token, positions)| {
+                positions.binary_search(&(start + token.position)).is_ok()
+            })
+        });
+    }
+    let length = phrase.tokens.last().map(|t| t.position).unwrap_or(0);
+    match min_span(lists) {
+        Some(span) => span <= length + phrase.slop,
+        None => false,
+    }
+}
+
+/**
+ * The smallest distance between the first and last position of a window containing at least one
+ * position from every list
+ */
+fn min_span(lists: &[&Vec<u32>]) -> Option<u32> {
+    let mut merged: Vec<(u32, usize)> = lists
+        .iter()
+        .enumerate()
+        .flat_map(|(i, positions)| positions.iter().map(move |p| (*p, i)))
+        .collect();
+    merged.sort_unstable();
+
+    let mut counts = vec![0; lists.len()];
+    let mut covered = 0;
+    let mut best: Option<u32> = None;
+    let mut start = 0;
+    for end in 0..merged.len() {
+        let (end_position, list) = merged[end];
+        if counts[list] == 0 {
+            covered += 1;
+        }
+        counts[list] += 1;
+        while covered == lists.len() {
+            let (start_position, start_list) = merged[start];
+            let span = end_position - start_position;
+            best = Some(best.map_or(span, |best| best.min(span)));
+            counts[start_list] -= 1;
+            if counts[start_list] == 0 {
+                covered -= 1;
+            }
+            start += 1;
+        }
+    }
+    best
+}
+
+/**
+ * tf-idf of the query tokens in the document, boosted by how close together they are
+ */
+fn score(cid: &str, postings: &HashMap<String, Postings>, total: f64, boosts: &Boosts) -> f64 {
+    let mut score = 0.0;
+    let mut lists = Vec::new();
+    for token_postings in postings.values() {
+        let Some(positions) = token_postings.get(cid) else {
+            continue;
+        };
+        let idf = (1.0 + total / token_postings.len() as f64).ln();
+        let frequency: f64 = positions
+            .iter()
+            .map(|position| boosts.get(Field::of(*position)))
+            .sum();
+        // sublinear in the frequency, but still linear in the boost below one occurrence
+        let tf = if frequency < 1.0 {
+            frequency
+        } else {
+            1.0 + frequency.ln()
+        };
+        score += tf * idf;
+        lists.push(positions);
+    }
+    if lists.len() > 1 {
+        if let Some(span) = min_span(&lists) {
+            // span is at least lists.len() - 1, when the terms are right next to each other
+            score *= 1.0 + (lists.len() - 1) as f64 / span.max(1) as f64;
+        }
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::Token;
+
+    fn phrase(positions: &[u32], slop: u32) -> Phrase {
+        Phrase {
+            tokens: positions
+                .iter()
+                .map(|p| Token {
+                    term: p.to_string(),
+                    position: *p,
+                })
+                .collect(),
+            slop,
+        }
+    }
+
+    #[test]
+    fn min_span_of_lists() {
+        assert_eq!(min_span(&[&vec![1, 10], &vec![4, 12], &vec![11]]), Some(2));
+        assert_eq!(min_span(&[&vec![5], &vec![1]]), Some(4));
+        assert_eq!(min_span(&[&vec![5], &vec![]]), None);
+    }
+
+    #[test]
+    fn exact_phrase() {
+        // "a b" where b directly follows a
+        assert!(phrase_matches(
+            &phrase(&[0, 1], 0),
+            &[&vec![3, 8], &vec![5, 9]]
+        ));
+        assert!(!phrase_matches(
+            &phrase(&[0, 1], 0),
+            &[&vec![3, 8], &vec![2, 5]]
+        ));
+        // stop word in between: "cat in the hat"
+        assert!(phrase_matches(&phrase(&[0, 3], 0), &[&vec![7], &vec![10]]));
+    }
+
+    #[test]
+    fn proximity() {
+        assert!(phrase_matches(&phrase(&[0, 1], 3), &[&vec![10], &vec![6]]));
+        assert!(!phrase_matches(&phrase(&[0, 1], 3), &[&vec![10], &vec![5]]));
+    }
+
+    #[test]
+    fn type_filters() {
+        assert!(matches_type("image/png", "image"));
+        assert!(matches_type("image/png", "Image/PNG"));
+        assert!(!matches_type("image/png", "image/jpeg"));
+        assert!(!matches_type("text/html", "tex"));
+        assert_eq!(size_bucket(9_999), "<10KB");
+        assert_eq!(size_bucket(10_000), "10KB-100KB");
+        assert_eq!(size_bucket(u64::MAX), ">10MB");
+    }
+}
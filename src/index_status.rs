@@ -0,0 +1,203 @@
+use crate::gateway::GatewayStats;
+use crate::index_queue::IndexQueue;
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// a measurement of the index this recent is reported again rather than measuring it again
+const MEASUREMENT_MAX_AGE: Duration = Duration::from_secs(60);
+
+#[derive(Serialize)]
+pub struct QueueStatus {
+    pub length: usize,
+    pub capacity: usize,
+}
+
+#[derive(Serialize)]
+pub struct IndexStatus {
+    pub items: usize,
+    pub aliases: usize,
+    pub keywords: usize,
+    pub links: usize,
+    pub linking_items: usize,
+}
+
+/**
+ * Estimated bytes taken up by each part of the index, as of the last time it was measured. With a
+ * budget that is done periodically, without one when a report asks for a measurement older than
+ * a minute.
+ */
+#[derive(Serialize)]
+pub struct IndexMemory {
+    // index results including their compressed text
+    pub map: u64,
+    pub keywords: u64,
+    pub keyword_rank: u64,
+    pub budget: Option<u64>,
+    pub evicted_texts: usize,
+    pub evicted_keywords: usize,
+}
+
+#[derive(Serialize)]
+pub struct MemoryStatus {
+    // resident memory of the process and memory available on the machine, None where /proc
+    // can't be read
+    pub rss: Option<u64>,
+    pub available: Option<u64>,
+    pub index: IndexMemory,
+}
+
+#[derive(Serialize)]
+pub struct StorageStatus {
+    pub data_dir: Option<PathBuf>,
+    // bytes of the files under data_dir
+    pub used: u64,
+}
+
+#[derive(Serialize)]
+pub struct InFlight {
+    pub item: String,
+    pub seconds: f64,
+}
+
+#[derive(Serialize)]
+pub struct WorkerStatus {
+    pub total: usize,
+    pub busy: usize,
+    pub idle: usize,
+    // items being fetched and indexed, the longest running first
+    pub fetching: Vec<InFlight>,
+}
+
+/**
+ * Everything /status reports about the running indexer
+ */
+#[derive(Serialize)]
+pub struct StatusReport {
+    pub uptime_seconds: u64,
+    pub queue: QueueStatus,
+    pub index: IndexStatus,
+    pub memory: MemoryStatus,
+    pub storage: StorageStatus,
+    pub workers: WorkerStatus,
+    pub gateways: Vec<GatewayStats>,
+}
+
+impl IndexQueue {
+    /**
+     * Everything /status reports. Without a memory budget this may measure the index, which takes
+     * a walk over all of it, so it shouldn't be called on an async worker.
+     */
+    pub fn status_report(&self) -> StatusReport {
+        // nothing measures the index periodically when it has no budget
+        if !self.memory.is_limited() {
+            self.measure_memory(MEASUREMENT_MAX_AGE);
+        }
+        let usage = self.memory.usage();
+        let (linking_items, links) = self.links.len();
+        let (total, busy) = self.metrics.workers();
+        StatusReport {
+            uptime_seconds: self.metrics.uptime().as_secs(),
+            queue: QueueStatus {
+                length: self.queue_length(),
+                capacity: self.queue.capacity(),
+            },
+            index: IndexStatus {
+                items: self.index_length(),
+                aliases: self.aliases.len(),
+                keywords: self.keyword_length(),
+                links,
+                linking_items,
+            },
+            memory: MemoryStatus {
+                rss: proc_kilobytes("/proc/self/status", "VmRSS:"),
+                available: proc_kilobytes("/proc/meminfo", "MemAvailable:"),
+                index: IndexMemory {
+                    map: usage.results + usage.bodies,
+                    keywords: usage.postings,
+                    keyword_rank: usage.keyword_rank,
+                    budget: usage.budget,
+                    evicted_texts: usage.evicted_bodies,
+                    evicted_keywords: usage.evicted_keywords,
+                },
+            },
+            storage: StorageStatus {
+                data_dir: self.data_dir.clone(),
+                used: self
+                    .data_dir
+                    .as_deref()
+                    .map_or(0, |dir| disk_usage(dir).unwrap_or(0)),
+            },
+            workers: WorkerStatus {
+                total,
+                busy,
+                idle: total.saturating_sub(busy),
+                fetching: self
+                    .metrics
+                    .in_flight()
+                    .into_iter()
+                    .map(|(item, elapsed)| InFlight {
+                        item,
+                        seconds: elapsed.as_secs_f64(),
+                    })
+                    .collect(),
+            },
+            gateways: self.gateways.stats(),
+        }
+    }
+}
+
+/**
+ * Reads a value given in kB from a /proc file, like the `VmRSS:  1234 kB` line of
+ * /proc/self/status, and returns it in bytes
+ */
+fn proc_kilobytes(path: &str, key: &str) -> Option<u64> {
+    parse_kilobytes(&fs::read_to_string(path).ok()?, key)
+}
+
+fn parse_kilobytes(text: &str, key: &str) -> Option<u64> {
+    let line = text.lines().find(|line| line.starts_with(key))?;
+    let kilobytes: u64 = line[key.len()..].split_whitespace().next()?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+/**
+ * Bytes taken up by the files in a directory and everything below it
+ */
+fn disk_usage(dir: &Path) -> io::Result<u64> {
+    let mut used = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            used += disk_usage(&entry.path())?;
+        } else if file_type.is_file() {
+            used += entry.metadata()?.len();
+        }
+    }
+    Ok(used)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_proc_values() {
+        let status = "Name:\tipfs_indexer\nVmPeak:\t  20480 kB\nVmRSS:\t   1024 kB\n";
+        assert_eq!(parse_kilobytes(status, "VmRSS:"), Some(1024 * 1024));
+        assert_eq!(parse_kilobytes(status, "VmSwap:"), None);
+    }
+
+    #[test]
+    fn sums_files_below_a_directory() {
+        let dir = std::env::temp_dir().join(format!("ipfs_indexer_du_{}", std::process::id()));
+        fs::create_dir_all(dir.join("cold")).unwrap();
+        fs::write(dir.join("seeds.txt"), "abc").unwrap();
+        fs::write(dir.join("cold").join("bodies.log"), "defgh").unwrap();
+        assert_eq!(disk_usage(&dir).unwrap(), 8);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
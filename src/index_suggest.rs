@@ -0,0 +1,253 @@
+use crate::analyzer::normalize;
+use crate::index_queue::IndexQueue;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::sync::RwLock;
+
+// distinct queries remembered, the counts are halved when there are more
+const MAX_QUERIES: usize = 10_000;
+const DEFAULT_LIMIT: usize = 10;
+
+#[derive(Deserialize)]
+pub struct SuggestParams {
+    pub prefix: String,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Suggestion {
+    pub text: String,
+    // number of documents the query or keyword was found in
+    pub documents: u32,
+    // how often it has been searched for
+    pub searches: u32,
+}
+
+#[derive(Clone, Copy, Default)]
+struct QueryStats {
+    searches: u32,
+    results: u32,
+}
+
+/**
+ * Counts of the searches that found something, so often searched queries can be suggested
+ */
+#[derive(Default)]
+pub struct QueryLog {
+    // normalized query -> stats, sorted to look up queries by prefix
+    queries: RwLock<BTreeMap<String, QueryStats>>,
+}
+
+impl QueryLog {
+    pub fn new() -> Self {
+        QueryLog::default()
+    }
+
+    pub fn record(&self, query: &str, results: usize) {
+        let query = normalize_query(query);
+        if query.is_empty() {
+            return;
+        }
+        let mut queries = self.queries.write().unwrap();
+        if queries.len() >= MAX_QUERIES && !queries.contains_key(&query) {
+            // age the counts so rare queries make room and new ones can still come in
+            queries.retain(|_, stats| {
+                stats.searches /= 2;
+                stats.searches > 0
+            });
+        }
+        let stats = queries.entry(query).or_default();
+        stats.searches += 1;
+        stats.results = results.try_into().unwrap_or(u32::MAX);
+    }
+
+    pub fn searches(&self, query: &str) -> u32 {
+        self.queries
+            .read()
+            .unwrap()
+            .get(query)
+            .map_or(0, |stats| stats.searches)
+    }
+
+    /**
+     * The logged queries starting with the (normalized) prefix
+     */
+    fn starting_with(&self, prefix: &str) -> Vec<Suggestion> {
+        self.queries
+            .read()
+            .unwrap()
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(query, _)| query.starts_with(prefix))
+            .map(|(query, stats)| Suggestion {
+                text: query.clone(),
+                documents: stats.results,
+                searches: stats.searches,
+            })
+            .collect()
+    }
+}
+
+pub fn normalize_query(query: &str) -> String {
+    normalize(query)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl IndexQueue {
+    /**
+     * Completions for what has been typed so far: past queries starting with it, and the
+     * indexed keywords completing its last word. Ranked by how often they have been searched for,
+     * then by the number of documents containing them.
+     */
+    pub fn suggest(&self, params: &SuggestParams) -> Vec<Suggestion> {
+        let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+        let prefix = normalize_query(&params.prefix);
+        if prefix.is_empty() || limit == 0 {
+            return Vec::new();
+        }
+
+        let mut suggestions: HashMap<String, Suggestion> = HashMap::new();
+        for suggestion in self.query_log.starting_with(&prefix) {
+            suggestions.insert(suggestion.text.clone(), suggestion);
+        }
+        // a trailing space means the last word is finished
+        if !params.prefix.ends_with(char::is_whitespace) {
+            let (before, word) = match prefix.rsplit_once(' ') {
+                Some((before, word)) => (format!("{} ", before), word),
+                None => (String::new(), prefix.as_str()),
+            };
+            for (word, documents) in self.completions(word, limit) {
+                let text = before.clone() + &word;
+                suggestions.entry(text.clone()).or_insert(Suggestion {
+                    searches: self.query_log.searches(&text),
+                    text,
+                    documents,
+                });
+            }
+        }
+
+        let mut suggestions: Vec<Suggestion> = suggestions.into_values().collect();
+        suggestions.sort_by(|a, b| {
+            b.searches
+                .cmp(&a.searches)
+                .then_with(|| b.documents.cmp(&a.documents))
+                .then_with(|| a.text.cmp(&b.text))
+        });
+        suggestions.truncate(limit);
+        suggestions
+    }
+
+    /**
+     * Indexed words starting with the prefix and the number of documents containing them. The
+     * index holds stems, which don't always start with what has been typed so far ("happy" is
+     * indexed as "happi"), so the stems of the prefix are looked up too. Every stem is shown as
+     * the word it is most often written as.
+     */
+    fn completions(&self, prefix: &str, limit: usize) -> Vec<(String, u32)> {
+        let mut words: HashMap<String, u32> = HashMap::new();
+        for variant in self.analyzer.query_variants(prefix, None) {
+            let found = self.keyword_rank.matching(
+                &variant,
+                |term| self.word_forms.word(term).starts_with(prefix),
+                limit,
+            );
+            for (term, documents) in found {
+                let documents_for_word = words.entry(self.word_forms.word(&term)).or_insert(0);
+                *documents_for_word = (*documents_for_word).max(documents);
+            }
+        }
+        words.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::word_forms::Words;
+
+    #[test]
+    fn ages_counts_when_full() {
+        let log = QueryLog::new();
+        log.record("popular", 1);
+        log.record("Popular ", 1);
+        for i in 0..MAX_QUERIES - 1 {
+            log.record(&format!("query {}", i), 1);
+        }
+        assert_eq!(log.searches("popular"), 2);
+        log.record("new query", 3);
+        assert_eq!(log.searches("popular"), 1);
+        assert_eq!(log.searches("query 1"), 0);
+        assert_eq!(log.searches("new query"), 1);
+    }
+
+    #[test]
+    fn suggests_queries_and_keywords() {
+        let queue = IndexQueue::new();
+        queue.keyword_rank.set("gateway", 5);
+        queue.keyword_rank.set("gather", 2);
+        queue.keyword_rank.set("garden", 9);
+        queue.keyword_rank.set("ipfs", 7);
+        queue.query_log.record("gather", 2);
+        queue.query_log.record("ipfs gateway list", 4);
+
+        let suggest = |prefix: &str| -> Vec<String> {
+            queue
+                .suggest(&SuggestParams {
+                    prefix: prefix.to_string(),
+                    limit: Some(3),
+                })
+                .into_iter()
+                .map(|suggestion| suggestion.text)
+                .collect()
+        };
+        assert_eq!(suggest("Ga"), vec!["gather", "garden", "gateway"]);
+        assert_eq!(suggest("gat"), vec!["gather", "gateway"]);
+        assert_eq!(
+            suggest("ipfs ga"),
+            vec!["ipfs gateway list", "ipfs garden", "ipfs gateway"]
+        );
+        assert_eq!(suggest("ipfs "), vec!["ipfs gateway list"]);
+        assert!(suggest("  ").is_empty());
+    }
+
+    #[test]
+    fn suggests_words_rather_than_stems() {
+        let queue = IndexQueue::new();
+        queue.keyword_rank.set("librari", 4);
+        queue.keyword_rank.set("happi", 2);
+        queue.keyword_rank.set("happen", 3);
+        let mut words = Words::new();
+        for (stem, word, count) in [
+            ("librari", "libraries", 2),
+            ("librari", "library", 5),
+            ("happi", "happy", 1),
+            ("happen", "happened", 1),
+        ] {
+            words
+                .entry(stem.to_string())
+                .or_default()
+                .insert(word.to_string(), count);
+        }
+        queue.word_forms.add(&words);
+
+        let suggest = |prefix: &str| -> Vec<(String, u32)> {
+            queue
+                .suggest(&SuggestParams {
+                    prefix: prefix.to_string(),
+                    limit: None,
+                })
+                .into_iter()
+                .map(|suggestion| (suggestion.text, suggestion.documents))
+                .collect()
+        };
+        assert_eq!(suggest("libr"), vec![("library".to_string(), 4)]);
+        assert_eq!(
+            suggest("happ"),
+            vec![("happened".to_string(), 3), ("happy".to_string(), 2)]
+        );
+        // the stem of the word typed so far is shorter than it
+        assert_eq!(suggest("happy"), vec![("happy".to_string(), 2)]);
+    }
+}
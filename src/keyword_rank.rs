@@ -0,0 +1,238 @@
+use std::cmp::Reverse;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+use std::hash::{Hash, Hasher};
+use std::ops::Bound;
+use std::sync::RwLock;
+
+// keywords are spread over this many separately locked rankings, so workers updating different
+// keywords don't wait on each other
+const SHARDS: usize = 16;
+
+#[derive(Default)]
+struct Ranking {
+    // keyword -> number of cids containing it, sorted so it doubles as the term dictionary
+    frequencies: BTreeMap<String, u32>,
+    // the same counts ordered from most to least frequent, ties broken alphabetically
+    ordered: BTreeSet<(Reverse<u32>, String)>,
+}
+
+/**
+ * Document frequency of every keyword, kept in order so the most common keywords can be read
+ * without sorting the whole vocabulary. The keywords are also kept alphabetically, so the ones
+ * starting with a prefix can be found without going through all of them.
+ *
+ * The keywords are split into shards by their hash. Reads go through every shard and merge what
+ * they find, updates only lock the shard of the keyword.
+ */
+pub struct KeywordRank {
+    shards: Vec<RwLock<Ranking>>,
+}
+
+impl Default for KeywordRank {
+    fn default() -> Self {
+        KeywordRank::new()
+    }
+}
+
+impl KeywordRank {
+    pub fn new() -> Self {
+        KeywordRank {
+            shards: (0..SHARDS).map(|_| RwLock::default()).collect(),
+        }
+    }
+
+    fn shard(&self, keyword: &str) -> &RwLock<Ranking> {
+        let mut hasher = DefaultHasher::new();
+        keyword.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % SHARDS]
+    }
+
+    /**
+     * Sets the number of cids containing the keyword, a count of 0 removes the keyword
+     */
+    pub fn set(&self, keyword: &str, count: u32) {
+        let mut ranking = self.shard(keyword).write().unwrap();
+        let previous = if count == 0 {
+            ranking.frequencies.remove(keyword)
+        } else {
+            ranking.frequencies.insert(keyword.to_string(), count)
+        };
+        if let Some(previous) = previous {
+            ranking
+                .ordered
+                .remove(&(Reverse(previous), keyword.to_string()));
+        }
+        if count > 0 {
+            ranking
+                .ordered
+                .insert((Reverse(count), keyword.to_string()));
+        }
+    }
+
+    #[cfg(test)]
+    pub fn get(&self, keyword: &str) -> Option<u32> {
+        self.shard(keyword)
+            .read()
+            .unwrap()
+            .frequencies
+            .get(keyword)
+            .cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.read().unwrap().frequencies.len())
+            .sum()
+    }
+
+    /**
+     * Rough number of bytes taken up, every keyword is kept twice along with its count
+     */
+    pub fn memory_size(&self) -> u64 {
+        self.shards
+            .iter()
+            .map(|shard| {
+                let ranking = shard.read().unwrap();
+                let keywords: usize = ranking.frequencies.keys().map(|word| word.len()).sum();
+                (2 * keywords + 80 * ranking.frequencies.len()) as u64
+            })
+            .sum()
+    }
+
+    /**
+     * Returns the n most frequent keywords with their counts
+     */
+    pub fn top(&self, n: usize) -> Vec<(String, u32)> {
+        let mut top: Vec<(Reverse<u32>, String)> = Vec::new();
+        for shard in &self.shards {
+            top.extend(shard.read().unwrap().ordered.iter().take(n).cloned());
+        }
+        top.sort_unstable();
+        top.into_iter()
+            .take(n)
+            .map(|(Reverse(count), keyword)| (keyword, count))
+            .collect()
+    }
+
+    /**
+     * Returns up to limit keywords starting with the prefix that the filter accepts, the most
+     * frequent first. Every match is looked at, only the best limit so far are kept.
+     */
+    pub fn matching(
+        &self,
+        prefix: &str,
+        filter: impl Fn(&str) -> bool,
+        limit: usize,
+    ) -> Vec<(String, u32)> {
+        if limit == 0 {
+            return Vec::new();
+        }
+        // the worst of the best matches on top, more frequent is better and then alphabetical
+        let mut best: BinaryHeap<Reverse<(u32, Reverse<String>)>> = BinaryHeap::new();
+        for shard in &self.shards {
+            let shard = shard.read().unwrap();
+            let matches = shard
+                .frequencies
+                .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+                .take_while(|(keyword, _)| keyword.starts_with(prefix));
+            for (keyword, &count) in matches {
+                if best.len() == limit {
+                    let Reverse((worst, Reverse(last))) = best.peek().unwrap();
+                    if count < *worst || (count == *worst && keyword >= last) {
+                        continue;
+                    }
+                }
+                if !filter(keyword) {
+                    continue;
+                }
+                best.push(Reverse((count, Reverse(keyword.clone()))));
+                if best.len() > limit {
+                    best.pop();
+                }
+            }
+        }
+        best.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((count, Reverse(keyword)))| (keyword, count))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn top_is_ordered_by_count() {
+        let rank = KeywordRank::new();
+        rank.set("a", 1);
+        rank.set("b", 3);
+        rank.set("c", 2);
+        assert_eq!(
+            rank.top(2),
+            vec![("b".to_string(), 3), ("c".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn top_merges_the_shards() {
+        let rank = KeywordRank::new();
+        for count in 1..=100 {
+            rank.set(&format!("word{}", count), count);
+        }
+        let top: Vec<u32> = rank.top(5).into_iter().map(|(_, count)| count).collect();
+        assert_eq!(top, vec![100, 99, 98, 97, 96]);
+        assert_eq!(rank.len(), 100);
+    }
+
+    #[test]
+    fn updates_replace_previous_count() {
+        let rank = KeywordRank::new();
+        rank.set("a", 1);
+        rank.set("b", 2);
+        rank.set("a", 5);
+        rank.set("b", 0);
+        assert_eq!(rank.top(10), vec![("a".to_string(), 5)]);
+        assert_eq!(rank.len(), 1);
+        assert_eq!(rank.get("b"), None);
+    }
+
+    #[test]
+    fn matching_prefix() {
+        let rank = KeywordRank::new();
+        rank.set("ipfs", 1);
+        rank.set("ipfix", 3);
+        rank.set("ip", 2);
+        rank.set("iq", 5);
+        assert_eq!(
+            rank.matching("ipf", |_| true, 10),
+            vec![("ipfix".to_string(), 3), ("ipfs".to_string(), 1)]
+        );
+        assert_eq!(
+            rank.matching("ip", |keyword| keyword.len() == 4, 10),
+            vec![("ipfs".to_string(), 1)]
+        );
+        assert_eq!(rank.matching("i", |_| true, 2).len(), 2);
+        assert!(rank.matching("x", |_| true, 10).is_empty());
+    }
+
+    #[test]
+    fn matching_finds_frequent_keywords_after_the_limit() {
+        let rank = KeywordRank::new();
+        for i in 0..100 {
+            rank.set(&format!("a{:03}", i), 1);
+        }
+        rank.set("apple", 50);
+        rank.set("azure", 20);
+        assert_eq!(
+            rank.matching("a", |_| true, 2),
+            vec![("apple".to_string(), 50), ("azure".to_string(), 20)]
+        );
+        assert_eq!(
+            rank.matching("a", |keyword| keyword != "apple", 1),
+            vec![("azure".to_string(), 20)]
+        );
+    }
+}
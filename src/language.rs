@@ -0,0 +1,85 @@
+use rust_stemmers::{Algorithm, Stemmer};
+use whatlang::Lang;
+
+// whatlang's own is_reliable() rejects most short pages, this lets through a clear best guess
+const MIN_CONFIDENCE: f64 = 0.5;
+
+/**
+ * Detects the language of a document. Returns the ISO 639-3 code (eng, deu, ...) or None if the
+ * text is too short or ambiguous to tell.
+ */
+pub fn detect(text: &str) -> Option<String> {
+    let info = whatlang::detect(text)?;
+    if info.confidence() >= MIN_CONFIDENCE {
+        Some(info.lang().code().to_string())
+    } else {
+        None
+    }
+}
+
+/**
+ * Returns the stemmer for a language (ISO 639-3 code) if there is one
+ */
+pub fn stemmer(code: &str) -> Option<Stemmer> {
+    algorithm(Lang::from_code(code)?).map(Stemmer::create)
+}
+
+/**
+ * The languages (ISO 639-3 codes) we can stem
+ */
+pub fn stemmed_languages() -> impl Iterator<Item = &'static str> {
+    Lang::all()
+        .iter()
+        .filter(|lang| algorithm(**lang).is_some())
+        .map(|lang| lang.code())
+}
+
+fn algorithm(lang: Lang) -> Option<Algorithm> {
+    match lang {
+        Lang::Ara => Some(Algorithm::Arabic),
+        Lang::Dan => Some(Algorithm::Danish),
+        Lang::Nld => Some(Algorithm::Dutch),
+        Lang::Eng => Some(Algorithm::English),
+        Lang::Fin => Some(Algorithm::Finnish),
+        Lang::Fra => Some(Algorithm::French),
+        Lang::Deu => Some(Algorithm::German),
+        Lang::Ell => Some(Algorithm::Greek),
+        Lang::Hun => Some(Algorithm::Hungarian),
+        Lang::Ita => Some(Algorithm::Italian),
+        Lang::Nob => Some(Algorithm::Norwegian),
+        Lang::Por => Some(Algorithm::Portuguese),
+        Lang::Ron => Some(Algorithm::Romanian),
+        Lang::Rus => Some(Algorithm::Russian),
+        Lang::Spa => Some(Algorithm::Spanish),
+        Lang::Swe => Some(Algorithm::Swedish),
+        Lang::Tam => Some(Algorithm::Tamil),
+        Lang::Tur => Some(Algorithm::Turkish),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_language() {
+        assert_eq!(
+            detect("The quick brown fox jumps over the lazy dog and runs into the forest"),
+            Some("eng".to_string())
+        );
+        assert_eq!(
+            detect("Der schnelle braune Fuchs springt über den faulen Hund und läuft in den Wald"),
+            Some("deu".to_string())
+        );
+        assert_eq!(detect("Renewable energy, solar."), None);
+    }
+
+    #[test]
+    fn stems_by_language() {
+        assert_eq!(stemmer("eng").unwrap().stem("running"), "run");
+        assert_eq!(stemmer("deu").unwrap().stem("häuser"), "haus");
+        assert!(stemmer("cmn").is_none());
+        assert!(stemmer("xyz").is_none());
+    }
+}
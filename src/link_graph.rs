@@ -0,0 +1,412 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+// probability of following a link rather than jumping to a random item
+const DAMPING: f64 = 0.85;
+const ITERATIONS: usize = 30;
+// how much authority can raise a search score, see LinkGraph::authority
+const AUTHORITY_WEIGHT: f64 = 0.5;
+
+/**
+ * A link from one item to another, with the text of the link
+ */
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Link {
+    pub target: String,
+    pub anchor: String,
+}
+
+// one line of the saved graph
+#[derive(Serialize, Deserialize)]
+struct Links {
+    source: String,
+    links: Vec<Link>,
+}
+
+#[derive(Default)]
+struct Graph {
+    // item -> the links on it
+    outlinks: HashMap<String, Vec<Link>>,
+    // item -> the items linking to it
+    inlinks: HashMap<String, HashSet<String>>,
+}
+
+/**
+ * The links between indexed items (the items they were queued as, cid or cid/path) and the
+ * PageRank computed over them. When a path is set the links are saved there (one JSON object per
+ * line) every time the ranks are computed, and loaded again on startup.
+ */
+pub struct LinkGraph {
+    path: Option<PathBuf>,
+    graph: RwLock<Graph>,
+    // item -> PageRank, scaled so the average item has a rank of 1
+    ranks: RwLock<HashMap<String, f64>>,
+}
+
+impl LinkGraph {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let graph = LinkGraph {
+            path,
+            graph: RwLock::new(Graph::default()),
+            ranks: RwLock::new(HashMap::new()),
+        };
+        if let Some(path) = graph.path.as_ref().filter(|path| path.exists()) {
+            match graph.load(path) {
+                Ok(sources) => info!("Loaded links of {} items", sources),
+                Err(err) => warn!("Error reading links from {}: {}", path.display(), err),
+            }
+        }
+        graph
+    }
+
+    /**
+     * Replaces the links of an item, returning the links it had before
+     */
+    pub fn set_links(&self, source: &str, links: Vec<Link>) -> Vec<Link> {
+        let mut graph = self.graph.write().unwrap();
+        let previous = graph.unlink(source);
+        for link in &links {
+            graph
+                .inlinks
+                .entry(link.target.clone())
+                .or_default()
+                .insert(source.to_string());
+        }
+        graph.outlinks.insert(source.to_string(), links);
+        previous
+    }
+
+    /**
+     * Removes the links of an item and returns them, links to it from other items stay
+     */
+    pub fn remove(&self, source: &str) -> Vec<Link> {
+        self.graph.write().unwrap().unlink(source)
+    }
+
+    pub fn outlinks(&self, source: &str) -> Vec<Link> {
+        self.graph
+            .read()
+            .unwrap()
+            .outlinks
+            .get(source)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /**
+     * The items linking to the target along with the text of the link, one entry per link
+     */
+    pub fn inlinks(&self, target: &str) -> Vec<(String, String)> {
+        let graph = self.graph.read().unwrap();
+        let Some(sources) = graph.inlinks.get(target) else {
+            return Vec::new();
+        };
+        let mut inlinks: Vec<(String, String)> = sources
+            .iter()
+            .flat_map(|source| {
+                graph.outlinks[source]
+                    .iter()
+                    .filter(|link| link.target == target)
+                    .map(|link| (source.clone(), link.anchor.clone()))
+            })
+            .collect();
+        inlinks.sort();
+        inlinks
+    }
+
+    /**
+     * The distinct texts of the links to the target, at most limit of them. Many pages link to
+     * the same item with the same text, so there are usually far fewer than links.
+     */
+    pub fn anchors(&self, target: &str, limit: usize) -> Vec<String> {
+        let graph = self.graph.read().unwrap();
+        let Some(sources) = graph.inlinks.get(target) else {
+            return Vec::new();
+        };
+        let mut anchors: Vec<String> = Vec::new();
+        for link in sources.iter().flat_map(|source| &graph.outlinks[source]) {
+            if link.target == target && !link.anchor.is_empty() && !anchors.contains(&link.anchor) {
+                anchors.push(link.anchor.clone());
+                if anchors.len() == limit {
+                    break;
+                }
+            }
+        }
+        // the sources are in no particular order, sort so the same anchors index the same way
+        anchors.sort();
+        anchors
+    }
+
+    pub fn inlink_count(&self, target: &str) -> usize {
+        self.graph
+            .read()
+            .unwrap()
+            .inlinks
+            .get(target)
+            .map_or(0, |sources| sources.len())
+    }
+
+    /**
+     * Number of items with links, and the number of links between them
+     */
+    pub fn len(&self) -> (usize, usize) {
+        let graph = self.graph.read().unwrap();
+        let links = graph.outlinks.values().map(|links| links.len()).sum();
+        (graph.outlinks.len(), links)
+    }
+
+    pub fn rank(&self, item: &str) -> f64 {
+        self.ranks.read().unwrap().get(item).cloned().unwrap_or(0.0)
+    }
+
+    /**
+     * What a search score is multiplied by for the item: 1 for items nothing links to, growing
+     * with the log of the rank so a few heavily linked items don't drown out the text match
+     */
+    pub fn authority(&self, item: &str) -> f64 {
+        1.0 + AUTHORITY_WEIGHT * self.rank(item).ln_1p()
+    }
+
+    /**
+     * Computes the PageRank of every item in the graph and saves the graph
+     */
+    pub fn update_ranks(&self) {
+        let ranks = {
+            let graph = self.graph.read().unwrap();
+            page_rank(&graph.outlinks)
+        };
+        info!("Computed ranks of {} items", ranks.len());
+        *self.ranks.write().unwrap() = ranks;
+        if let Err(err) = self.save() {
+            warn!("Error saving links: {}", err);
+        }
+    }
+
+    fn load(&self, path: &Path) -> io::Result<usize> {
+        let reader = BufReader::new(fs::File::open(path)?);
+        let mut sources = 0;
+        for line in reader.lines() {
+            let links: Links = serde_json::from_str(&line?)?;
+            self.set_links(&links.source, links.links);
+            sources += 1;
+        }
+        Ok(sources)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // serialize first so the workers aren't kept waiting on the disk
+        let mut contents = Vec::new();
+        for (source, links) in &self.graph.read().unwrap().outlinks {
+            let line = Links {
+                source: source.clone(),
+                links: links.clone(),
+            };
+            serde_json::to_writer(&mut contents, &line)?;
+            contents.push(b'\n');
+        }
+        // write to a temporary file first so a crash mid-write doesn't lose the graph
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, path)
+    }
+}
+
+impl Graph {
+    fn unlink(&mut self, source: &str) -> Vec<Link> {
+        let Some(links) = self.outlinks.remove(source) else {
+            return Vec::new();
+        };
+        for link in &links {
+            if let Some(sources) = self.inlinks.get_mut(&link.target) {
+                sources.remove(source);
+                if sources.is_empty() {
+                    self.inlinks.remove(&link.target);
+                }
+            }
+        }
+        links
+    }
+}
+
+/**
+ * The targets whose anchor texts differ between two sets of links from the same item
+ */
+pub fn changed_targets(old: &[Link], new: &[Link]) -> Vec<String> {
+    fn by_target(links: &[Link]) -> HashMap<&str, Vec<&str>> {
+        let mut anchors: HashMap<&str, Vec<&str>> = HashMap::new();
+        for link in links {
+            anchors
+                .entry(link.target.as_str())
+                .or_default()
+                .push(link.anchor.as_str());
+        }
+        for texts in anchors.values_mut() {
+            texts.sort_unstable();
+        }
+        anchors
+    }
+    let (old, new) = (by_target(old), by_target(new));
+    let mut changed: Vec<String> = old
+        .keys()
+        .chain(new.keys())
+        .filter(|target| old.get(*target) != new.get(*target))
+        .map(|target| target.to_string())
+        .collect();
+    changed.sort();
+    changed.dedup();
+    changed
+}
+
+/**
+ * PageRank by power iteration. Items without links spread their rank over every item. Ranks are
+ * scaled by the number of items so they average 1 whatever the size of the graph.
+ */
+fn page_rank(outlinks: &HashMap<String, Vec<Link>>) -> HashMap<String, f64> {
+    let mut ids: HashMap<&str, usize> = HashMap::new();
+    for (source, links) in outlinks {
+        for item in std::iter::once(source).chain(links.iter().map(|link| &link.target)) {
+            let next = ids.len();
+            ids.entry(item.as_str()).or_insert(next);
+        }
+    }
+    let n = ids.len();
+    if n == 0 {
+        return HashMap::new();
+    }
+    // distinct targets of every item, a page linking to the same item twice votes once
+    let mut edges: Vec<Vec<usize>> = vec![Vec::new(); n];
+    for (source, links) in outlinks {
+        let targets = &mut edges[ids[source.as_str()]];
+        for link in links {
+            targets.push(ids[link.target.as_str()]);
+        }
+        targets.sort_unstable();
+        targets.dedup();
+    }
+
+    let mut rank = vec![1.0 / n as f64; n];
+    for _ in 0..ITERATIONS {
+        let dangling: f64 = (0..n)
+            .filter(|i| edges[*i].is_empty())
+            .map(|i| rank[i])
+            .sum();
+        let base = (1.0 - DAMPING) / n as f64 + DAMPING * dangling / n as f64;
+        let mut next = vec![base; n];
+        for (source, targets) in edges.iter().enumerate() {
+            let share = DAMPING * rank[source] / targets.len().max(1) as f64;
+            for target in targets {
+                next[*target] += share;
+            }
+        }
+        rank = next;
+    }
+    ids.into_iter()
+        .map(|(item, id)| (item.to_string(), rank[id] * n as f64))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn links(targets: &[&str]) -> Vec<Link> {
+        targets
+            .iter()
+            .map(|target| Link {
+                target: target.to_string(),
+                anchor: String::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tracks_inlinks() {
+        let graph = LinkGraph::new(None);
+        graph.set_links("a", links(&["b", "c"]));
+        graph.set_links("b", links(&["c"]));
+        assert_eq!(graph.inlink_count("c"), 2);
+        assert_eq!(
+            graph.inlinks("c"),
+            vec![
+                ("a".to_string(), String::new()),
+                ("b".to_string(), String::new())
+            ]
+        );
+        graph.set_links("a", links(&["b"]));
+        assert_eq!(graph.inlink_count("c"), 1);
+        graph.remove("b");
+        assert_eq!(graph.inlink_count("c"), 0);
+        assert_eq!(graph.inlink_count("b"), 1);
+        assert_eq!(graph.len(), (1, 1));
+    }
+
+    #[test]
+    fn anchor_texts() {
+        let graph = LinkGraph::new(None);
+        let link = |target: &str, anchor: &str| Link {
+            target: target.to_string(),
+            anchor: anchor.to_string(),
+        };
+        graph.set_links("a", vec![link("c", "Solar"), link("c", "")]);
+        graph.set_links("b", vec![link("c", "Solar"), link("c", "Panels")]);
+        assert_eq!(graph.anchors("c", 10), vec!["Panels", "Solar"]);
+        assert_eq!(graph.anchors("c", 1).len(), 1);
+        assert!(graph.anchors("a", 10).is_empty());
+
+        let previous = graph.set_links("b", vec![link("c", "Panels"), link("d", "")]);
+        assert_eq!(
+            changed_targets(&previous, &graph.outlinks("b")),
+            vec!["c", "d"]
+        );
+        assert!(changed_targets(&previous, &previous).is_empty());
+    }
+
+    #[test]
+    fn ranks_linked_items_higher() {
+        let graph = LinkGraph::new(None);
+        graph.set_links("a", links(&["hub"]));
+        graph.set_links("b", links(&["hub", "c"]));
+        graph.set_links("c", links(&["hub"]));
+        graph.set_links("hub", links(&["a"]));
+        graph.update_ranks();
+        assert!(graph.rank("hub") > graph.rank("a"));
+        assert!(graph.rank("a") > graph.rank("b"));
+        let total: f64 = ["a", "b", "c", "hub"].iter().map(|i| graph.rank(i)).sum();
+        assert!((total - 4.0).abs() < 1e-6);
+        assert_eq!(graph.authority("unknown"), 1.0);
+        assert!(graph.authority("hub") > graph.authority("b"));
+    }
+
+    #[test]
+    fn saves_and_loads() {
+        let dir = std::env::temp_dir().join(format!("ipfs_indexer_links_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("links.jsonl");
+
+        let graph = LinkGraph::new(Some(path.clone()));
+        graph.set_links(
+            "a",
+            vec![Link {
+                target: "b".to_string(),
+                anchor: "the b page".to_string(),
+            }],
+        );
+        graph.update_ranks();
+
+        let loaded = LinkGraph::new(Some(path));
+        assert_eq!(loaded.outlinks("a"), graph.outlinks("a"));
+        assert_eq!(loaded.inlink_count("b"), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,26 +1,70 @@
-use std::convert::TryFrom;
 use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
 use threadpool::ThreadPool;
 
-use actix_web::{get, web, App, HttpResponse, HttpServer};
-use cid::Cid;
+use actix_web::{delete, get, http::header, post, web, App, HttpRequest, HttpResponse, HttpServer};
 use log::{info, warn};
+use serde::Serialize;
 use simple_logger::SimpleLogger;
 
-use crate::index_queue::IndexQueue;
+use crate::click_log::ClickParams;
+use crate::config::{Config, USAGE};
+use crate::crawl_log::{CrawlRecord, EnqueuedBy};
+use crate::index_queue::{is_valid_item, CrawlStatus, EnqueueStatus, IndexQueue};
+use crate::index_search::SearchParams;
+use crate::index_suggest::SuggestParams;
 
+mod analyzer;
+mod click_log;
+mod cold_store;
+mod config;
+mod content;
+mod crawl_log;
+mod field;
+mod gateway;
+mod index_metrics;
 mod index_queue;
 mod index_result;
+mod index_search;
+mod index_status;
+mod index_suggest;
+mod keyword_rank;
+mod language;
+mod link_graph;
+mod memory;
+mod pattern;
+mod query;
+mod seeds;
+mod simhash;
+mod snippet;
+mod word_forms;
+
+// crawled when no other seeds are configured
+const DEFAULT_SEED: &str = "bafybeiaysi4s6lnjev27ln5icwm6tueaw2vdykrtjkwiphwekaywqhcjze";
+
+// large enough for a bulk enqueue of a few hundred thousand cids
+const MAX_BODY_SIZE: usize = 32 * 1024 * 1024;
+
+// how often the memory taken up by the index is measured, and kept within the budget
+const MEMORY_INTERVAL: Duration = Duration::from_secs(30);
 
 #[get("/status")]
 async fn status(queue: web::Data<IndexQueue>) -> HttpResponse {
-    HttpResponse::Ok().body(format!(
-        "Queue length: {} Index size: {} Number of Keywords: {}",
-        queue.queue_length(),
-        queue.index_length(),
-        queue.keyword_length()
-    ))
+    // the report may measure the whole index, which is kept off the async workers
+    match web::block(move || queue.status_report()).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(err) => HttpResponse::InternalServerError().body(format!("Error: {}", err)),
+    }
+}
+
+// for Prometheus to scrape
+#[get("/metrics")]
+async fn metrics(queue: web::Data<IndexQueue>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(queue.metrics_text())
 }
 
 // returns the top keywords
@@ -32,20 +76,229 @@ async fn keywords(queue: web::Data<IndexQueue>) -> HttpResponse {
 #[get("/enqueue/{item}")]
 async fn enqueue(data: web::Data<IndexQueue>, item: web::Path<String>) -> HttpResponse {
     let item = item.into_inner();
-    data.enqueue(item.clone());
+    data.enqueue(item.clone(), EnqueuedBy::Api);
     HttpResponse::Ok().body(format!("Enqueued {}", item))
 }
 
+#[derive(Serialize)]
+struct EnqueueResult {
+    item: String,
+    status: EnqueueStatus,
+}
+
+/**
+ * Queues many items at once. The body is either a JSON array of cids / cid paths or a newline
+ * separated list of them. Responds with the status of every item.
+ */
+#[post("/enqueue")]
+async fn bulk_enqueue(data: web::Data<IndexQueue>, body: String) -> HttpResponse {
+    let items: Vec<String> = if body.trim_start().starts_with('[') {
+        match serde_json::from_str(&body) {
+            Ok(items) => items,
+            Err(err) => return HttpResponse::BadRequest().body(format!("Invalid JSON: {}", err)),
+        }
+    } else {
+        body.lines().map(|line| line.to_string()).collect()
+    };
+
+    let results: Vec<EnqueueResult> = items
+        .iter()
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            let item = item.strip_prefix("/ipfs/").unwrap_or(item).to_string();
+            let item_status = if is_valid_item(&item) {
+                data.enqueue(item.clone(), EnqueuedBy::Api)
+            } else {
+                EnqueueStatus::Invalid
+            };
+            EnqueueResult {
+                item,
+                status: item_status,
+            }
+        })
+        .collect();
+    HttpResponse::Ok().json(results)
+}
+
+// fetches and indexes the item again, replacing the current result once it has been retrieved
+#[post("/reindex/{item:.*}")]
+async fn reindex(data: web::Data<IndexQueue>, item: web::Path<String>) -> HttpResponse {
+    let item = item.into_inner();
+    if !is_valid_item(&item) {
+        return HttpResponse::BadRequest().body(format!("Invalid CID {}", item));
+    }
+    match data.reindex(item.clone()) {
+        EnqueueStatus::Queued => HttpResponse::Ok().body(format!("Re-indexing {}", item)),
+        EnqueueStatus::AlreadyQueued => {
+            HttpResponse::Ok().body(format!("{} is already queued", item))
+        }
+        _ => HttpResponse::ServiceUnavailable().body("Queue is full"),
+    }
+}
+
+#[delete("/index/{item:.*}")]
+async fn remove(data: web::Data<IndexQueue>, item: web::Path<String>) -> HttpResponse {
+    let item = item.into_inner();
+    if !is_valid_item(&item) {
+        return HttpResponse::BadRequest().body(format!("Invalid CID {}", item));
+    }
+    // queued or being fetched, it is kept out of the index though there is nothing to take out
+    let pending =
+        data.crawl_status(&item) == CrawlStatus::Queued || data.metrics.is_working_on(&item);
+    match data.remove(&item) {
+        Some(_) => HttpResponse::Ok().body(format!("Removed {}", item)),
+        None if pending => HttpResponse::Ok().body(format!("Removed {} from the crawl", item)),
+        None => HttpResponse::NotFound().body(format!("{} is not indexed", item)),
+    }
+}
+
+#[get("/seeds")]
+async fn list_seeds(data: web::Data<IndexQueue>) -> HttpResponse {
+    HttpResponse::Ok().body(format!("Seeds: {:?}", data.seeds.list()))
+}
+
+// adds a seed and queues it right away
+#[post("/seeds/{cid}")]
+async fn add_seed(data: web::Data<IndexQueue>, cid: web::Path<String>) -> HttpResponse {
+    let cid = cid.into_inner();
+    if !is_valid_item(&cid) {
+        return HttpResponse::BadRequest().body(format!("Invalid CID {}", cid));
+    }
+    match data.seeds.add(cid.clone()) {
+        Ok(true) => {
+            data.enqueue(cid.clone(), EnqueuedBy::Seed);
+            HttpResponse::Ok().body(format!("Added seed {}", cid))
+        }
+        Ok(false) => HttpResponse::Ok().body(format!("{} is already a seed", cid)),
+        Err(err) => {
+            HttpResponse::InternalServerError().body(format!("Error saving seeds: {}", err))
+        }
+    }
+}
+
+#[delete("/seeds/{cid}")]
+async fn remove_seed(data: web::Data<IndexQueue>, cid: web::Path<String>) -> HttpResponse {
+    let cid = cid.into_inner();
+    match data.seeds.remove(&cid) {
+        Ok(true) => HttpResponse::Ok().body(format!("Removed seed {}", cid)),
+        Ok(false) => HttpResponse::NotFound().body(format!("{} is not a seed", cid)),
+        Err(err) => {
+            HttpResponse::InternalServerError().body(format!("Error saving seeds: {}", err))
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct LinkedItem {
+    item: String,
+    anchor: String,
+    status: CrawlStatus,
+}
+
+#[derive(Serialize)]
+struct ItemLinks {
+    item: String,
+    status: CrawlStatus,
+    links: Vec<LinkedItem>,
+}
+
+// the links on an item, and how far the linked items are in the crawl
+#[get("/outlinks/{item:.*}")]
+async fn outlinks(data: web::Data<IndexQueue>, item: web::Path<String>) -> HttpResponse {
+    let item = item.into_inner();
+    let links = data
+        .links
+        .outlinks(&item)
+        .into_iter()
+        .map(|link| LinkedItem {
+            status: data.crawl_status(&link.target),
+            item: link.target,
+            anchor: link.anchor,
+        })
+        .collect();
+    HttpResponse::Ok().json(ItemLinks {
+        status: data.crawl_status(&item),
+        item,
+        links,
+    })
+}
+
+// the indexed items linking to an item, with the text of their links
+#[get("/backlinks/{item:.*}")]
+async fn backlinks(data: web::Data<IndexQueue>, item: web::Path<String>) -> HttpResponse {
+    let item = item.into_inner();
+    let links = data
+        .links
+        .inlinks(&item)
+        .into_iter()
+        .map(|(source, anchor)| LinkedItem {
+            status: data.crawl_status(&source),
+            item: source,
+            anchor,
+        })
+        .collect();
+    HttpResponse::Ok().json(ItemLinks {
+        status: data.crawl_status(&item),
+        item,
+        links,
+    })
+}
+
+#[derive(Serialize)]
+struct CrawlHistory {
+    item: String,
+    status: CrawlStatus,
+    events: Vec<CrawlRecord>,
+}
+
+// what happened to an item in the crawl, to tell why it was or wasn't indexed
+#[get("/crawl/{item:.*}")]
+async fn crawl_history(data: web::Data<IndexQueue>, item: web::Path<String>) -> HttpResponse {
+    let item = item.into_inner();
+    HttpResponse::Ok().json(CrawlHistory {
+        status: data.crawl_status(&item),
+        events: data.crawl_log.history(&item),
+        item,
+    })
+}
+
+// optionally filtered by language with ?lang=eng, paged with ?limit=20&offset=0
 #[get("/search/{query}")]
-async fn search(data: web::Data<IndexQueue>, item: web::Path<String>) -> HttpResponse {
+async fn search(
+    data: web::Data<IndexQueue>,
+    item: web::Path<String>,
+    params: web::Query<SearchParams>,
+) -> HttpResponse {
     let query = item.into_inner();
     info!("Searching for {}", query.clone());
-    let results = data.search(query.clone());
-    if results.is_empty() {
-        HttpResponse::Ok().body(format!("No results found for {}", query))
-    } else {
-        HttpResponse::Ok().body(format!("Results for {}: {:?}", query, results))
+    HttpResponse::Ok().json(data.search(query, &params))
+}
+
+// link search results here to count clicks, /click?q=solar&cid=somecid redirects to the public
+// gateway. Repeated clicks from the same address only count once a day.
+#[get("/click")]
+async fn click(
+    req: HttpRequest,
+    data: web::Data<IndexQueue>,
+    params: web::Query<ClickParams>,
+) -> HttpResponse {
+    if !is_valid_item(&params.cid) {
+        return HttpResponse::BadRequest().body(format!("Invalid CID {}", params.cid));
     }
+    // the address the request came from, forwarded-for headers are easily made up
+    let client = req
+        .peer_addr()
+        .map_or_else(String::new, |addr| addr.ip().to_string());
+    HttpResponse::Found()
+        .insert_header((header::LOCATION, data.click(&client, &params)))
+        .finish()
+}
+
+// completions for a search box, /suggest?prefix=ipf&limit=10
+#[get("/suggest")]
+async fn suggest(data: web::Data<IndexQueue>, params: web::Query<SuggestParams>) -> HttpResponse {
+    HttpResponse::Ok().json(data.suggest(&params))
 }
 
 #[actix_web::main]
@@ -56,19 +309,58 @@ async fn main() -> std::io::Result<()> {
     //otherwise run with log level set via RUST_LOG=info ./ipfs_indexer
     SimpleLogger::new().env().init().unwrap();
 
-    let mut gateway: String = "ipfs.io".to_string();
     let args: Vec<String> = std::env::args().collect();
     if args.len() < 2 {
-        warn!("Running with ipfs.io gateway. Usage: ipfs_indexer <ipfs_node_address>");
-    } else {
-        info!("Running with ifps gateway {}", args[1]);
-        gateway = args[1].clone();
+        warn!("Running with ipfs.io gateway. {}", USAGE);
+    }
+    let config = Config::from_args(&args[1..]);
+    info!(
+        "Running with ipfs gateways {:?} ({:?} selection)",
+        config.gateways, config.selection
+    );
+
+    let index_queue = web::Data::new(IndexQueue::with_config(&config));
+    if let Some(seeds_file) = &config.seeds_file {
+        if let Err(err) = index_queue.seeds.load_file(seeds_file) {
+            warn!("Error loading seeds from {}: {}", seeds_file.display(), err);
+        }
+    }
+    // a saved list that is empty was emptied on purpose, the default isn't added back
+    if index_queue.seeds.is_new() && index_queue.seeds.is_empty() {
+        info!("No seeds configured, starting from {}", DEFAULT_SEED);
+        if let Err(err) = index_queue.seeds.add(DEFAULT_SEED.to_string()) {
+            warn!("Error saving seeds: {}", err);
+        }
     }
+    index_queue.reseed();
 
-    let index_queue = web::Data::new(IndexQueue::new());
-    let wikipedia_cid =
-        Cid::try_from("bafybeiaysi4s6lnjev27ln5icwm6tueaw2vdykrtjkwiphwekaywqhcjze").unwrap();
-    index_queue.enqueue(wikipedia_cid.to_string());
+    if !config.reseed_interval.is_zero() {
+        let reseed_queue = Arc::clone(&index_queue);
+        let interval = config.reseed_interval;
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            info!("Re-crawling seeds");
+            reseed_queue.reseed();
+        });
+    }
+
+    if !config.pagerank_interval.is_zero() {
+        let rank_queue = Arc::clone(&index_queue);
+        let interval = config.pagerank_interval;
+        thread::spawn(move || loop {
+            rank_queue.links.update_ranks();
+            thread::sleep(interval);
+        });
+    }
+
+    // without a budget there is nothing to evict, /status measures the index when asked
+    if index_queue.memory.is_limited() {
+        let memory_queue = Arc::clone(&index_queue);
+        thread::spawn(move || loop {
+            thread::sleep(MEMORY_INTERVAL);
+            memory_queue.update_memory();
+        });
+    }
 
     // if we don't have multiple workers, we can get the case where we run out of room in the
     // queue if a doc has many links
@@ -78,19 +370,31 @@ async fn main() -> std::io::Result<()> {
     // todo: find some way to shutdown the pool when the server stops
     for _ in 0..n_workers {
         let inner_config = Arc::clone(&index_queue);
-        let inner_gateway = gateway.clone();
         pool.execute(move || {
-            inner_config.start(inner_gateway);
+            inner_config.start();
         });
     }
 
     HttpServer::new(move || {
         App::new()
             .app_data(index_queue.clone())
+            .app_data(web::PayloadConfig::new(MAX_BODY_SIZE))
             .service(status)
+            .service(metrics)
             .service(enqueue)
+            .service(bulk_enqueue)
+            .service(reindex)
+            .service(remove)
             .service(search)
+            .service(suggest)
+            .service(click)
+            .service(outlinks)
+            .service(backlinks)
+            .service(crawl_history)
             .service(keywords)
+            .service(list_seeds)
+            .service(add_seed)
+            .service(remove_seed)
     })
     .bind("0.0.0.0:9090")?
     .run()
@@ -102,6 +406,7 @@ mod tests {
     use actix_web::{test, web, App};
 
     use super::*;
+    use crate::link_graph::Link;
 
     #[actix_web::test]
     async fn test_status_get() {
@@ -112,8 +417,12 @@ mod tests {
         let app =
             test::init_service(App::new().app_data(index_queue.clone()).service(status)).await;
 
-        let resp = test::call_service(&app, req).await;
-        assert!(resp.status().is_success());
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["queue"]["length"], 0);
+        assert_eq!(resp["index"]["items"], 0);
+        assert_eq!(resp["workers"]["fetching"], serde_json::json!([]));
+        assert!(resp["memory"]["index"]["keyword_rank"].is_u64());
+        assert!(resp["uptime_seconds"].is_u64());
     }
 
     #[actix_web::test]
@@ -131,6 +440,128 @@ mod tests {
         assert!(resp.status().is_success());
     }
 
+    #[actix_web::test]
+    async fn test_bulk_enqueue() {
+        let index_queue = web::Data::new(IndexQueue::new());
+        let app = test::init_service(
+            App::new()
+                .app_data(index_queue.clone())
+                .service(bulk_enqueue),
+        )
+        .await;
+
+        let body = format!(
+            r#"["{0}", "{0}/wiki/Home.html", "{0}", "notacid"]"#,
+            DEFAULT_SEED
+        );
+        let req = test::TestRequest::post()
+            .uri("/enqueue")
+            .insert_header(("content-type", "application/json"))
+            .set_payload(body)
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let statuses: Vec<&str> = resp
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["status"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            statuses,
+            vec!["queued", "queued", "already_queued", "invalid"]
+        );
+
+        let body = format!(
+            "{}\n\n/ipfs/{}/wiki/Other.html\n",
+            DEFAULT_SEED, DEFAULT_SEED
+        );
+        let req = test::TestRequest::post()
+            .uri("/enqueue")
+            .set_payload(body)
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp[0]["status"], "already_queued");
+        assert_eq!(resp[1]["status"], "queued");
+        assert_eq!(index_queue.queue_length(), 3);
+    }
+
+    #[actix_web::test]
+    async fn test_reindex_and_remove() {
+        let index_queue = web::Data::new(IndexQueue::new());
+        let app = test::init_service(
+            App::new()
+                .app_data(index_queue.clone())
+                .service(reindex)
+                .service(remove),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/reindex/{}/wiki/Home.html", DEFAULT_SEED))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert_eq!(index_queue.queue_length(), 1);
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/index/{}", DEFAULT_SEED))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+        assert!(index_queue.removed.is_empty());
+
+        // queued, so it is kept out of the crawl
+        let req = test::TestRequest::delete()
+            .uri(&format!("/index/{}/wiki/Home.html", DEFAULT_SEED))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert_eq!(index_queue.removed.len(), 1);
+
+        let req = test::TestRequest::delete()
+            .uri("/index/notacid")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_client_error());
+        assert_eq!(index_queue.removed.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_seeds() {
+        let index_queue = web::Data::new(IndexQueue::new());
+        let app = test::init_service(
+            App::new()
+                .app_data(index_queue.clone())
+                .service(list_seeds)
+                .service(add_seed)
+                .service(remove_seed),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/seeds/{}", DEFAULT_SEED))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert_eq!(index_queue.seeds.list(), vec![DEFAULT_SEED]);
+        assert_eq!(index_queue.queue_length(), 1);
+
+        let req = test::TestRequest::post().uri("/seeds/notacid").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_client_error());
+
+        let req = test::TestRequest::get().uri("/seeds").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert!(String::from_utf8_lossy(&body).contains(DEFAULT_SEED));
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/seeds/{}", DEFAULT_SEED))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert!(index_queue.seeds.is_empty());
+    }
+
     #[actix_web::test]
     async fn test_search_get() {
         let index_queue = web::Data::new(IndexQueue::new());
@@ -144,5 +575,127 @@ mod tests {
 
         let resp = test::call_service(&app, req).await;
         assert!(resp.status().is_success());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["query"], "searchItem");
+        assert_eq!(body["total"], 0);
+        assert!(body["hits"].as_array().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_suggest_get() {
+        let index_queue = web::Data::new(IndexQueue::new());
+        index_queue.keyword_rank.set("gateway", 2);
+        let app =
+            test::init_service(App::new().app_data(index_queue.clone()).service(suggest)).await;
+
+        let req = test::TestRequest::get()
+            .uri("/suggest?prefix=Gat")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body[0]["text"], "gateway");
+        assert_eq!(body[0]["documents"], 2);
+
+        let req = test::TestRequest::get().uri("/suggest").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_client_error());
+    }
+
+    #[actix_web::test]
+    async fn test_metrics_get() {
+        let index_queue = web::Data::new(IndexQueue::new());
+        let app =
+            test::init_service(App::new().app_data(index_queue.clone()).service(metrics)).await;
+        let req = test::TestRequest::get().uri("/metrics").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let body = test::read_body(resp).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("# TYPE ipfs_indexer_items_total counter\n"));
+        assert!(body.contains("ipfs_indexer_index_size 0\n"));
+    }
+
+    #[actix_web::test]
+    async fn test_click_get() {
+        let index_queue = web::Data::new(IndexQueue::new());
+        let app = test::init_service(App::new().app_data(index_queue.clone()).service(click)).await;
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/click?q=solar&cid={}/wiki", DEFAULT_SEED))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::FOUND);
+        let location = resp.headers().get(header::LOCATION).unwrap();
+        assert_eq!(
+            location.to_str().unwrap(),
+            format!("https://ipfs.io/ipfs/{}/wiki", DEFAULT_SEED)
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/click?q=solar&cid=nonsense")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_client_error());
+    }
+
+    #[actix_web::test]
+    async fn test_links_get() {
+        let index_queue = web::Data::new(IndexQueue::new());
+        index_queue.links.set_links(
+            "a",
+            vec![Link {
+                target: "b/page.html".to_string(),
+                anchor: "Page".to_string(),
+            }],
+        );
+        index_queue.enqueue("b/page.html".to_string(), EnqueuedBy::Api);
+        let app = test::init_service(
+            App::new()
+                .app_data(index_queue.clone())
+                .service(outlinks)
+                .service(backlinks),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/outlinks/a").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["status"], "not_indexed");
+        assert_eq!(body["links"][0]["item"], "b/page.html");
+        assert_eq!(body["links"][0]["anchor"], "Page");
+        assert_eq!(body["links"][0]["status"], "queued");
+
+        let req = test::TestRequest::get()
+            .uri("/backlinks/b/page.html")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["status"], "queued");
+        assert_eq!(body["links"][0]["item"], "a");
+        assert_eq!(body["links"][0]["anchor"], "Page");
+    }
+
+    #[actix_web::test]
+    async fn test_crawl_history_get() {
+        let index_queue = web::Data::new(IndexQueue::new());
+        let app = test::init_service(
+            App::new()
+                .app_data(index_queue.clone())
+                .service(enqueue)
+                .service(crawl_history),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/enqueue/a").to_request();
+        test::call_service(&app, req).await;
+        let req = test::TestRequest::get().uri("/crawl/a").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["status"], "queued");
+        assert_eq!(body["events"][0]["event"], "enqueued");
+        assert_eq!(body["events"][0]["by"], "api");
+
+        let req = test::TestRequest::get()
+            .uri("/crawl/b/page.html")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["status"], "not_indexed");
+        assert_eq!(body["events"], serde_json::json!([]));
     }
 }
@@ -0,0 +1,371 @@
+use crate::cold_store::ColdStore;
+use crate::index_queue::IndexQueue;
+use crate::index_result::IndexResult;
+use dashmap::mapref::entry::Entry;
+use dashmap::mapref::one::Ref;
+use dashmap::DashMap;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// eviction goes this far below the budget, so it doesn't have to run again right away
+const LOW_WATER: f64 = 0.9;
+
+// postings of a keyword: cid -> positions
+type Postings = HashMap<String, Vec<u32>>;
+
+/**
+ * Estimated memory taken up by the index, and what has been moved to disk
+ */
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct MemoryUsage {
+    // index results, without their text
+    pub results: u64,
+    // compressed text of the documents
+    pub bodies: u64,
+    // keyword -> cids -> positions
+    pub postings: u64,
+    // keyword -> number of cids, it can't be evicted so it doesn't count towards the budget
+    pub keyword_rank: u64,
+    pub budget: Option<u64>,
+    pub evicted_bodies: usize,
+    pub evicted_keywords: usize,
+    // size of the files the evicted data is in
+    pub disk: u64,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> u64 {
+        self.results + self.bodies + self.postings
+    }
+}
+
+struct Cold {
+    postings: ColdStore,
+    bodies: ColdStore,
+}
+
+/**
+ * Keeps the index within a memory budget by moving the least recently used document texts and
+ * posting lists to disk. They are read back in when a search or an update needs them.
+ */
+pub struct MemoryBudget {
+    // bytes, and where to put what doesn't fit. Both are None when memory isn't limited
+    limit: Option<u64>,
+    cold: Option<Cold>,
+    // ticks on every use, to tell what was used least recently
+    clock: AtomicU64,
+    // keyword / item -> clock at their last use
+    keywords_used: DashMap<String, u64>,
+    bodies_used: DashMap<String, u64>,
+    // as of the last update
+    usage: Mutex<MemoryUsage>,
+    // when the index was last measured on request, without a budget nothing measures it otherwise
+    measured: Mutex<Option<Instant>>,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: Option<u64>, data_dir: Option<&Path>) -> Self {
+        let (limit, cold) = match (limit, data_dir) {
+            (Some(limit), Some(dir)) => {
+                let dir = dir.join("cold");
+                let cold = Cold {
+                    postings: ColdStore::new(dir.join("postings.log")),
+                    bodies: ColdStore::new(dir.join("bodies.log")),
+                };
+                (Some(limit), Some(cold))
+            }
+            (Some(_), None) => {
+                warn!("A memory budget needs a data directory to evict to, not limiting memory");
+                (None, None)
+            }
+            _ => (None, None),
+        };
+        MemoryBudget {
+            limit,
+            cold,
+            clock: AtomicU64::new(0),
+            keywords_used: DashMap::new(),
+            bodies_used: DashMap::new(),
+            usage: Mutex::new(MemoryUsage {
+                budget: limit,
+                ..MemoryUsage::default()
+            }),
+            measured: Mutex::new(None),
+        }
+    }
+
+    /**
+     * Whether there is a budget to keep to, without one nothing is ever evicted
+     */
+    pub fn is_limited(&self) -> bool {
+        self.limit.is_some()
+    }
+
+    pub fn usage(&self) -> MemoryUsage {
+        *self.usage.lock().unwrap()
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn touch_keyword(&self, word: &str) {
+        if self.limit.is_some() {
+            self.keywords_used.insert(word.to_string(), self.tick());
+        }
+    }
+
+    fn touch_body(&self, item: &str) {
+        if self.limit.is_some() {
+            self.bodies_used.insert(item.to_string(), self.tick());
+        }
+    }
+
+    /**
+     * Drops the use of a keyword that is no longer in the index
+     */
+    pub fn forget_keyword(&self, word: &str) {
+        self.keywords_used.remove(word);
+    }
+
+    /**
+     * Takes the postings of the keyword back out of cold storage
+     */
+    fn thaw_postings(&self, word: &str) -> Option<Postings> {
+        let cold = self.cold.as_ref()?;
+        let bytes = match cold.postings.take(word) {
+            Ok(bytes) => bytes?,
+            Err(err) => {
+                warn!("Error reading the postings of {}: {}", word, err);
+                return None;
+            }
+        };
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    /**
+     * Drops the evicted text of an item that is removed or replaced
+     */
+    pub fn forget_body(&self, item: &str) {
+        self.bodies_used.remove(item);
+        if let Some(cold) = &self.cold {
+            if let Err(err) = cold.bodies.remove(item) {
+                warn!("Error removing the text of {}: {}", item, err);
+            }
+        }
+    }
+}
+
+/**
+ * Rough number of bytes a value takes up in memory, counting the String and HashMap overheads
+ */
+fn result_size(result: &IndexResult) -> u64 {
+    let strings = result.cid.len()
+        + result.title.len()
+        + result.excerpt.len()
+        + result.root.len()
+        + result.content_type.len()
+        + result
+            .aliases
+            .iter()
+            .map(|alias| alias.len() + 24)
+            .sum::<usize>();
+    let keywords: usize = result
+        .keywords
+        .keys()
+        .chain(result.anchor_keywords.keys())
+        .map(|word| word.len() + 40)
+        .sum();
+    (strings + keywords + 256) as u64
+}
+
+fn postings_size(word: &str, postings: &Postings) -> u64 {
+    let cids: usize = postings
+        .iter()
+        .map(|(cid, positions)| cid.len() + 4 * positions.len() + 56)
+        .sum();
+    (word.len() + cids + 64) as u64
+}
+
+enum Evictable {
+    Body,
+    Keyword,
+}
+
+impl IndexQueue {
+    /**
+     * The entry of a keyword in the keyword map, with its postings read back in from disk if they
+     * were evicted. Every change to the keyword map has to go through here, or it would shadow
+     * the evicted postings.
+     */
+    pub fn keyword_entry(&self, word: &str) -> Entry<'_, String, Postings> {
+        let entry = match self.keywords.entry(word.to_string()) {
+            Entry::Vacant(entry) => match self.memory.thaw_postings(word) {
+                Some(postings) => Entry::Occupied(entry.insert_entry(postings)),
+                None => return Entry::Vacant(entry),
+            },
+            entry => entry,
+        };
+        self.memory.touch_keyword(word);
+        entry
+    }
+
+    /**
+     * The postings of a keyword, read back in from disk if they were evicted
+     */
+    pub fn keyword_postings(&self, word: &str) -> Option<Ref<'_, String, Postings>> {
+        // only keywords that are in the index are touched, searches for anything else are not
+        // remembered
+        if let Some(postings) = self.keywords.get(word) {
+            self.memory.touch_keyword(word);
+            return Some(postings);
+        }
+        // releases the entry before looking the postings up again
+        if let Entry::Vacant(_) = self.keyword_entry(word) {
+            return None;
+        }
+        self.keywords.get(word)
+    }
+
+    /**
+     * The text of an indexed document, read from disk if it was evicted
+     */
+    pub fn document_text(&self, item: &str, result: &IndexResult) -> String {
+        self.memory.touch_body(item);
+        if !result.body.is_empty() {
+            return result.text();
+        }
+        let Some(cold) = &self.memory.cold else {
+            return String::new();
+        };
+        match cold.bodies.get(item) {
+            Ok(Some(body)) => IndexResult::inflate(&body),
+            Ok(None) => String::new(),
+            Err(err) => {
+                warn!("Error reading the text of {}: {}", item, err);
+                String::new()
+            }
+        }
+    }
+
+    /**
+     * Measures the memory taken up by the index unless it was measured less than max_age ago.
+     * Requests arriving while it is measured wait for it rather than measuring again.
+     */
+    pub fn measure_memory(&self, max_age: Duration) {
+        let mut measured = self.memory.measured.lock().unwrap();
+        if measured.is_some_and(|at| at.elapsed() < max_age) {
+            return;
+        }
+        self.update_memory();
+        *measured = Some(Instant::now());
+    }
+
+    /**
+     * Measures the memory taken up by the index and, when it is over the budget, moves the least
+     * recently used document texts and posting lists to disk until it is well under it
+     */
+    pub fn update_memory(&self) {
+        let mut usage = MemoryUsage {
+            budget: self.memory.limit,
+            ..MemoryUsage::default()
+        };
+        // when each piece was last used, how big it is and what it is. Without a budget the index
+        // is only measured
+        let limited = self.memory.is_limited();
+        let mut evictable: Vec<(u64, u64, Evictable, String)> = Vec::new();
+        let used = |times: &DashMap<String, u64>, key: &str| times.get(key).map_or(0, |t| *t);
+        for result in self.map.iter() {
+            usage.results += result_size(&result);
+            usage.bodies += result.body.len() as u64;
+            if limited && !result.body.is_empty() {
+                evictable.push((
+                    used(&self.memory.bodies_used, result.key()),
+                    result.body.len() as u64,
+                    Evictable::Body,
+                    result.key().clone(),
+                ));
+            }
+        }
+        usage.keyword_rank = self.keyword_rank.memory_size();
+        for postings in self.keywords.iter() {
+            let size = postings_size(postings.key(), &postings);
+            usage.postings += size;
+            if limited {
+                evictable.push((
+                    used(&self.memory.keywords_used, postings.key()),
+                    size,
+                    Evictable::Keyword,
+                    postings.key().clone(),
+                ));
+            }
+        }
+
+        if let (Some(limit), Some(cold)) = (self.memory.limit, &self.memory.cold) {
+            if usage.total() > limit {
+                let target = (limit as f64 * LOW_WATER) as u64;
+                evictable.sort_unstable_by_key(|(used, ..)| *used);
+                let (mut bodies, mut keywords) = (0, 0);
+                for (last_used, size, kind, key) in evictable {
+                    if usage.total() <= target {
+                        break;
+                    }
+                    // skip anything used since it was looked at above
+                    let evicted = match kind {
+                        Evictable::Body => {
+                            let Some(mut result) = self.map.get_mut(&key) else {
+                                continue;
+                            };
+                            if used(&self.memory.bodies_used, &key) > last_used {
+                                continue;
+                            }
+                            let stored = cold.bodies.put(&key, &result.body);
+                            if stored.is_ok() {
+                                result.body = Vec::new();
+                                usage.bodies -= size;
+                                bodies += 1;
+                            }
+                            stored
+                        }
+                        Evictable::Keyword => {
+                            let Entry::Occupied(entry) = self.keywords.entry(key.clone()) else {
+                                continue;
+                            };
+                            if used(&self.memory.keywords_used, &key) > last_used {
+                                continue;
+                            }
+                            let stored = serde_json::to_vec(entry.get())
+                                .map_err(io::Error::from)
+                                .and_then(|bytes| cold.postings.put(&key, &bytes));
+                            if stored.is_ok() {
+                                entry.remove();
+                                usage.postings -= size;
+                                keywords += 1;
+                            }
+                            stored
+                        }
+                    };
+                    if let Err(err) = evicted {
+                        warn!("Error evicting {}: {}", key, err);
+                        break;
+                    }
+                }
+                info!(
+                    "Evicted the text of {} documents and the postings of {} keywords",
+                    bodies, keywords
+                );
+            }
+            (usage.evicted_bodies, usage.disk) = cold.bodies.len();
+            let (evicted_keywords, disk) = cold.postings.len();
+            usage.evicted_keywords = evicted_keywords;
+            usage.disk += disk;
+        }
+        *self.memory.usage.lock().unwrap() = usage;
+    }
+}
@@ -0,0 +1,121 @@
+/**
+ * Matches a term against a wildcard pattern, where * stands for any number of characters and ?
+ * for exactly one
+ */
+pub fn wildcard_matches(pattern: &str, term: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let term: Vec<char> = term.chars().collect();
+    // position in the pattern and term right after the last *, to backtrack to
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut t) = (0, 0);
+    while t < term.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == term[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            p += 1;
+            star = Some((p, t));
+        } else if let Some((star_p, star_t)) = star {
+            // let the last * take one more character
+            p = star_p;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/**
+ * The part of a wildcard pattern before the first wildcard, every match starts with it
+ */
+pub fn literal_prefix(pattern: &str) -> &str {
+    let end = pattern.find(['*', '?']).unwrap_or(pattern.len());
+    &pattern[..end]
+}
+
+/**
+ * How many typos to allow in a term: none for very short terms, where almost anything would be
+ * a match, one up to 5 characters and two for longer terms
+ */
+pub fn max_edits(term: &str) -> u32 {
+    match term.chars().count() {
+        0..=2 => 0,
+        3..=5 => 1,
+        _ => 2,
+    }
+}
+
+/**
+ * Number of single character insertions, deletions, substitutions and swaps of two neighbouring
+ * characters it takes to turn a into b, or None if it is more than max
+ */
+pub fn edit_distance(a: &str, b: &str, max: u32) -> Option<u32> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let max = max as usize;
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+    // rows of the distance matrix: two back, previous and current
+    let mut before: Vec<usize> = vec![0; b.len() + 1];
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current: Vec<usize> = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        current[0] = i;
+        let mut row_min = i;
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(previous[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(before[j - 2] + 1);
+            }
+            current[j] = distance;
+            row_min = row_min.min(distance);
+        }
+        if row_min > max {
+            return None;
+        }
+        std::mem::swap(&mut before, &mut previous);
+        std::mem::swap(&mut previous, &mut current);
+    }
+    let distance = previous[b.len()];
+    (distance <= max).then_some(distance as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_matches("ipf*", "ipfs"));
+        assert!(wildcard_matches("ipf*", "ipf"));
+        assert!(wildcard_matches("i?fs", "ipfs"));
+        assert!(wildcard_matches("*fs", "ipfs"));
+        assert!(wildcard_matches("i*f*s", "interplanetaryfs"));
+        assert!(!wildcard_matches("i?fs", "ifs"));
+        assert!(!wildcard_matches("ipf*", "ip"));
+        assert!(!wildcard_matches("*fs", "ipfsx"));
+        assert!(wildcard_matches("文*", "文件"));
+        assert_eq!(literal_prefix("ipf*s"), "ipf");
+        assert_eq!(literal_prefix("?pfs"), "");
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("ipfs", "ipfs", 2), Some(0));
+        assert_eq!(edit_distance("ipfs", "ipf", 2), Some(1));
+        assert_eq!(edit_distance("ipfs", "ipsf", 2), Some(1));
+        assert_eq!(edit_distance("kitten", "sitting", 3), Some(3));
+        assert_eq!(edit_distance("kitten", "sitting", 2), None);
+        assert_eq!(edit_distance("gateway", "gate", 2), None);
+        assert_eq!(edit_distance("über", "uber", 1), Some(1));
+        assert_eq!(max_edits("ab"), 0);
+        assert_eq!(max_edits("ipfs"), 1);
+        assert_eq!(max_edits("gateway"), 2);
+    }
+}
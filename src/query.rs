@@ -0,0 +1,259 @@
+use crate::analyzer::{normalize, Analyzer, Token};
+use crate::field::Field;
+use crate::pattern;
+
+/**
+ * Words that have to appear together: next to each other in the same order for an exact phrase
+ * ("ipfs gateway"), or all within slop extra words of each other in any order ("ipfs gateway"~5).
+ */
+#[derive(Debug, PartialEq)]
+pub struct Phrase {
+    // positions are relative to the first word of the phrase
+    pub tokens: Vec<Token>,
+    pub slop: u32,
+}
+
+/**
+ * A term or phrase, along with the field it has to be in (title:ipfs) if any. Wildcard patterns
+ * (ipf*, i?fs) and fuzzy terms (ipfs~, ipfs~1, with the number of typos allowed) match every
+ * indexed term they fit.
+ */
+#[derive(Debug, PartialEq)]
+pub enum Clause {
+    Term(String, Option<Field>),
+    Phrase(Phrase, Option<Field>),
+    Wildcard(String, Option<Field>),
+    Fuzzy(String, u32, Option<Field>),
+}
+
+/**
+ * A parsed search query. Every clause has to match for a document to be returned.
+ */
+#[derive(Debug, PartialEq)]
+pub struct Query {
+    pub clauses: Vec<Clause>,
+}
+
+impl Query {
+    pub fn parse(analyzer: &Analyzer, query: &str) -> Query {
+        let mut clauses = Vec::new();
+        let mut rest = query;
+        while let Some(start) = rest.find('"') {
+            let (before, field) = field_prefix(&rest[..start]);
+            add_terms(analyzer, before, &mut clauses);
+            let after = &rest[start + 1..];
+            // an unclosed quote runs to the end of the query
+            let (phrase, remainder) = match after.find('"') {
+                Some(end) => (&after[..end], &after[end + 1..]),
+                None => (after, ""),
+            };
+            let (slop, remainder) = parse_slop(remainder);
+            add_phrase(analyzer, phrase, slop, field, &mut clauses);
+            rest = remainder;
+        }
+        add_terms(analyzer, rest, &mut clauses);
+        Query { clauses }
+    }
+}
+
+fn add_terms(analyzer: &Analyzer, text: &str, clauses: &mut Vec<Clause>) {
+    for word in text.split_whitespace() {
+        let (word, field) = match word.split_once(':') {
+            Some((name, rest)) if Field::parse(name).is_some() => (rest, Field::parse(name)),
+            _ => (word, None),
+        };
+        if word.contains(['*', '?']) {
+            if let Some(pattern) = wildcard_pattern(word) {
+                clauses.push(Clause::Wildcard(pattern, field));
+            }
+            continue;
+        }
+        if let Some((term, distance)) = fuzzy_term(analyzer, word) {
+            clauses.push(Clause::Fuzzy(term, distance, field));
+            continue;
+        }
+        for term in analyzer.tokens(word) {
+            clauses.push(Clause::Term(term, field));
+        }
+    }
+}
+
+/**
+ * Normalizes a wildcard pattern like the analyzer would, dropping punctuation. None if there is
+ * nothing but wildcards left, that would match every term.
+ */
+fn wildcard_pattern(word: &str) -> Option<String> {
+    let pattern: String = normalize(word)
+        .chars()
+        .filter(|c| c.is_alphanumeric() || *c == '*' || *c == '?')
+        .collect();
+    pattern
+        .chars()
+        .any(char::is_alphanumeric)
+        .then_some(pattern)
+}
+
+/**
+ * Reads a word~ or word~N fuzzy term, the distance is at most 2 and depends on the length of the
+ * term when left out
+ */
+fn fuzzy_term(analyzer: &Analyzer, word: &str) -> Option<(String, u32)> {
+    let (word, distance) = word.rsplit_once('~')?;
+    let distance: Option<u32> = match distance {
+        "" => None,
+        digits => Some(digits.parse::<u32>().ok()?.min(2)),
+    };
+    let mut tokens = analyzer.tokens(word);
+    if tokens.len() != 1 {
+        return None;
+    }
+    let term = tokens.remove(0);
+    let distance = distance.unwrap_or_else(|| pattern::max_edits(&term));
+    Some((term, distance))
+}
+
+fn add_phrase(
+    analyzer: &Analyzer,
+    text: &str,
+    slop: u32,
+    field: Option<Field>,
+    clauses: &mut Vec<Clause>,
+) {
+    let mut tokens = analyzer.analyze(text);
+    match tokens.len() {
+        0 => {}
+        1 => clauses.push(Clause::Term(tokens.remove(0).term, field)),
+        _ => {
+            let first = tokens[0].position;
+            for token in tokens.iter_mut() {
+                token.position -= first;
+            }
+            clauses.push(Clause::Phrase(Phrase { tokens, slop }, field));
+        }
+    }
+}
+
+/**
+ * Splits a field name written right before a phrase (title:"...") off the end of the text
+ */
+fn field_prefix(text: &str) -> (&str, Option<Field>) {
+    let start = text.rfind(char::is_whitespace).map_or(0, |space| space + 1);
+    match text[start..].strip_suffix(':').and_then(Field::parse) {
+        Some(field) => (&text[..start], Some(field)),
+        None => (text, None),
+    }
+}
+
+/**
+ * Reads the ~N proximity suffix following a closing quote, returns the slop and the rest of the
+ * query
+ */
+fn parse_slop(text: &str) -> (u32, &str) {
+    let Some(rest) = text.strip_prefix('~') else {
+        return (0, text);
+    };
+    let digits = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    (rest[..digits].parse().unwrap_or(0), &rest[digits..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(term: &str, position: u32) -> Token {
+        Token {
+            term: term.to_string(),
+            position,
+        }
+    }
+
+    #[test]
+    fn terms_and_phrases() {
+        let analyzer = Analyzer::new(2, 64);
+        let query = Query::parse(&analyzer, r#"solar "cat in the hat" wind"#);
+        assert_eq!(
+            query.clauses,
+            vec![
+                Clause::Term("solar".to_string(), None),
+                Clause::Phrase(
+                    Phrase {
+                        tokens: vec![token("cat", 0), token("hat", 3)],
+                        slop: 0
+                    },
+                    None
+                ),
+                Clause::Term("wind".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn proximity_and_unclosed_quotes() {
+        let analyzer = Analyzer::new(2, 64);
+        let query = Query::parse(&analyzer, r#""ipfs gateway"~5 "single" "open quote"#);
+        assert_eq!(
+            query.clauses,
+            vec![
+                Clause::Phrase(
+                    Phrase {
+                        tokens: vec![token("ipfs", 0), token("gateway", 1)],
+                        slop: 5
+                    },
+                    None
+                ),
+                Clause::Term("single".to_string(), None),
+                Clause::Phrase(
+                    Phrase {
+                        tokens: vec![token("open", 0), token("quote", 1)],
+                        slop: 0
+                    },
+                    None
+                ),
+            ]
+        );
+        assert!(Query::parse(&analyzer, r#"the "of a""#).clauses.is_empty());
+    }
+
+    #[test]
+    fn field_restrictions() {
+        let analyzer = Analyzer::new(2, 64);
+        let query = Query::parse(
+            &analyzer,
+            r#"title:ipfs heading:"file system" http://x.org alt:"cat""#,
+        );
+        assert_eq!(
+            query.clauses,
+            vec![
+                Clause::Term("ipfs".to_string(), Some(Field::Title)),
+                Clause::Phrase(
+                    Phrase {
+                        tokens: vec![token("file", 0), token("system", 1)],
+                        slop: 0
+                    },
+                    Some(Field::Heading)
+                ),
+                Clause::Term("http".to_string(), None),
+                Clause::Term("org".to_string(), None),
+                Clause::Term("cat".to_string(), Some(Field::Alt)),
+            ]
+        );
+    }
+
+    #[test]
+    fn wildcard_and_fuzzy_terms() {
+        let analyzer = Analyzer::new(2, 64);
+        let query = Query::parse(&analyzer, "IPF* title:g?te* * gatway~ ipfs~1 ipfs~9 a-b~");
+        assert_eq!(
+            query.clauses,
+            vec![
+                Clause::Wildcard("ipf*".to_string(), None),
+                Clause::Wildcard("g?te*".to_string(), Some(Field::Title)),
+                Clause::Fuzzy("gatway".to_string(), 2, None),
+                Clause::Fuzzy("ipfs".to_string(), 1, None),
+                Clause::Fuzzy("ipfs".to_string(), 2, None),
+            ]
+        );
+    }
+}
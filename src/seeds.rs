@@ -0,0 +1,194 @@
+use crate::index_queue::is_valid_item;
+use log::{info, warn};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/**
+ * The CIDs the crawl starts from. When a path is set the list is saved there on every change
+ * (one CID per line) so it survives a restart.
+ */
+pub struct SeedList {
+    path: Option<PathBuf>,
+    // no list had been saved when it was created, an empty saved list was emptied on purpose
+    new: bool,
+    seeds: Mutex<BTreeSet<String>>,
+}
+
+impl SeedList {
+    /**
+     * Creates the seed list, loading the previously saved seeds from the path if there are any.
+     */
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let new = path.as_ref().is_none_or(|path| !path.exists());
+        let seeds = match &path {
+            Some(path) if path.exists() => match read_seeds(path) {
+                Ok(seeds) => seeds,
+                Err(err) => {
+                    warn!("Error reading seeds from {}: {}", path.display(), err);
+                    BTreeSet::new()
+                }
+            },
+            _ => BTreeSet::new(),
+        };
+        SeedList {
+            path,
+            new,
+            seeds: Mutex::new(seeds),
+        }
+    }
+
+    /**
+     * Adds the seeds listed in a file (one per line, blank lines and lines starting with # are
+     * ignored). Lines that aren't a valid cid are logged and skipped. Returns the number of new
+     * seeds.
+     */
+    pub fn load_file(&self, path: &Path) -> io::Result<usize> {
+        let (loaded, invalid): (BTreeSet<String>, BTreeSet<String>) = read_seeds(path)?
+            .into_iter()
+            .partition(|seed| is_valid_item(seed));
+        for seed in invalid {
+            warn!("Skipping invalid seed {} in {}", seed, path.display());
+        }
+        let mut seeds = self.seeds.lock().unwrap();
+        let before = seeds.len();
+        seeds.extend(loaded);
+        let added = seeds.len() - before;
+        if added > 0 {
+            self.save(&seeds)?;
+        }
+        info!("Loaded {} new seeds from {}", added, path.display());
+        Ok(added)
+    }
+
+    /**
+     * Adds a seed, returns false if it was already in the list.
+     */
+    pub fn add(&self, seed: String) -> io::Result<bool> {
+        let mut seeds = self.seeds.lock().unwrap();
+        if !seeds.insert(seed) {
+            return Ok(false);
+        }
+        self.save(&seeds)?;
+        Ok(true)
+    }
+
+    /**
+     * Removes a seed, returns false if it wasn't in the list.
+     */
+    pub fn remove(&self, seed: &str) -> io::Result<bool> {
+        let mut seeds = self.seeds.lock().unwrap();
+        if !seeds.remove(seed) {
+            return Ok(false);
+        }
+        self.save(&seeds)?;
+        Ok(true)
+    }
+
+    pub fn list(&self) -> Vec<String> {
+        self.seeds.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seeds.lock().unwrap().is_empty()
+    }
+
+    /**
+     * Whether no list had been saved before this one was created, so none was ever configured
+     */
+    pub fn is_new(&self) -> bool {
+        self.new
+    }
+
+    fn save(&self, seeds: &BTreeSet<String>) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents: String = seeds.iter().map(|seed| seed.clone() + "\n").collect();
+        // write to a temporary file first so a crash mid-write doesn't lose the list
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, path)
+    }
+}
+
+fn read_seeds(path: &Path) -> io::Result<BTreeSet<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("ipfs_indexer_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn add_and_remove() {
+        let seeds = SeedList::new(None);
+        assert!(seeds.add("a".to_string()).unwrap());
+        assert!(!seeds.add("a".to_string()).unwrap());
+        assert_eq!(seeds.list(), vec!["a"]);
+        assert!(seeds.remove("a").unwrap());
+        assert!(!seeds.remove("a").unwrap());
+        assert!(seeds.is_empty());
+    }
+
+    #[test]
+    fn persisted_across_instances() {
+        let dir = temp_dir("seeds");
+        let path = dir.join("seeds.txt");
+        let seeds = SeedList::new(Some(path.clone()));
+        seeds.add("b".to_string()).unwrap();
+        seeds.add("a".to_string()).unwrap();
+
+        assert!(seeds.is_new());
+
+        let reloaded = SeedList::new(Some(path.clone()));
+        assert_eq!(reloaded.list(), vec!["a", "b"]);
+        // emptied on purpose, it stays that way
+        reloaded.remove("a").unwrap();
+        reloaded.remove("b").unwrap();
+        let emptied = SeedList::new(Some(path));
+        assert!(emptied.is_empty());
+        assert!(!emptied.is_new());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    const WIKIPEDIA: &str = "bafybeiaysi4s6lnjev27ln5icwm6tueaw2vdykrtjkwiphwekaywqhcjze";
+    const DOCS: &str = "QmXoypizjW3WknFiJnKLwHCnL72vedxjQkDDP1mXWo6uco";
+
+    #[test]
+    fn load_file_skips_comments_and_invalid_cids() {
+        let dir = temp_dir("seed_file");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("list.txt");
+        fs::write(
+            &file,
+            format!(
+                "# wikipedia\n{}\n\n  {}  \n{}\nnot-a-cid\n",
+                WIKIPEDIA, DOCS, WIKIPEDIA
+            ),
+        )
+        .unwrap();
+
+        let seeds = SeedList::new(None);
+        assert_eq!(seeds.load_file(&file).unwrap(), 2);
+        assert_eq!(seeds.list(), vec![DOCS, WIKIPEDIA]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
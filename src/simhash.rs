@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+// fingerprints that differ in at most this many bits are near duplicates
+pub const MAX_DISTANCE: u32 = 3;
+// fewer distinct keywords than this say too little about a document to compare it
+const MIN_KEYWORDS: usize = 10;
+// the fingerprint is split into MAX_DISTANCE + 1 bands, near duplicates share at least one
+pub const BANDS: usize = 4;
+
+/**
+ * SimHash of a document's keywords, weighted by how often they occur. Every keyword hash votes on
+ * every bit of the fingerprint, so documents sharing most of their keywords end up with
+ * fingerprints differing in only a few bits. None for documents with too few keywords.
+ */
+pub fn fingerprint(keywords: &HashMap<String, u32>) -> Option<u64> {
+    if keywords.len() < MIN_KEYWORDS {
+        return None;
+    }
+    let mut votes = [0i64; 64];
+    for (keyword, count) in keywords {
+        let hash = fnv1a(keyword.as_bytes());
+        for (bit, vote) in votes.iter_mut().enumerate() {
+            if hash >> bit & 1 == 1 {
+                *vote += *count as i64;
+            } else {
+                *vote -= *count as i64;
+            }
+        }
+    }
+    Some(
+        votes
+            .iter()
+            .enumerate()
+            .filter(|(_, vote)| **vote > 0)
+            .fold(0, |fingerprint, (bit, _)| fingerprint | 1 << bit),
+    )
+}
+
+pub fn is_near_duplicate(a: u64, b: u64) -> bool {
+    (a ^ b).count_ones() <= MAX_DISTANCE
+}
+
+/**
+ * The bands of a fingerprint along with their index. Two fingerprints that differ in at most
+ * MAX_DISTANCE bits have at least one band in common, so only the fingerprints sharing a band
+ * need to be compared.
+ */
+pub fn bands(fingerprint: u64) -> [(usize, u16); BANDS] {
+    std::array::from_fn(|band| (band, (fingerprint >> (band * 16)) as u16))
+}
+
+// a hash that stays the same across runs and rust versions
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keywords(text: &str) -> HashMap<String, u32> {
+        let mut keywords = HashMap::new();
+        for word in text.split_whitespace() {
+            *keywords.entry(word.to_string()).or_insert(0) += 1;
+        }
+        keywords
+    }
+
+    const TEXT: &str = "the interplanetary file system is a protocol and peer to peer network for \
+        storing and sharing data in a distributed file system content addressing is used to \
+        uniquely identify each file in a global namespace connecting all computing devices";
+
+    #[test]
+    fn near_duplicates() {
+        let original = fingerprint(&keywords(TEXT)).unwrap();
+        let copy = fingerprint(&keywords(&TEXT.replace("global", "worldwide"))).unwrap();
+        let other = fingerprint(&keywords(
+            "solar panels convert sunlight into electricity using photovoltaic cells made from \
+             silicon and the power is stored in batteries for use at night",
+        ))
+        .unwrap();
+        assert!(is_near_duplicate(original, copy));
+        assert!(!is_near_duplicate(original, other));
+        assert_eq!(fingerprint(&keywords("too short")), None);
+    }
+
+    #[test]
+    fn near_duplicates_share_a_band() {
+        let original = 0x0123_4567_89ab_cdef;
+        let copy = original ^ 1 ^ 1 << 20 ^ 1 << 40;
+        assert!(is_near_duplicate(original, copy));
+        assert_eq!(bands(original)[3], bands(copy)[3]);
+        assert_ne!(bands(original)[0], bands(copy)[0]);
+    }
+}
@@ -0,0 +1,244 @@
+use crate::analyzer::{is_unspaced, Analyzer};
+use std::collections::HashSet;
+use std::ops::Range;
+
+// length of a snippet in bytes, roughly
+const SNIPPET_LENGTH: usize = 200;
+// how much of the snippet goes before the first match
+const LEADING_CONTEXT: usize = SNIPPET_LENGTH / 4;
+
+const HIGHLIGHT_START: &str = "<mark>";
+const HIGHLIGHT_END: &str = "</mark>";
+
+/**
+ * A part of the text that matched one of the query terms
+ */
+struct Match {
+    range: Range<usize>,
+    term: String,
+}
+
+/**
+ * Picks the part of the document text with the most query terms in it and wraps the words
+ * matching a term in <mark></mark>. The rest of the text is html escaped so the snippet can be
+ * shown as is. Terms are compared after analysis, so "Running" in the text matches a search for
+ * "runs".
+ *
+ * terms holds every form of the query tokens that could be in the index (see
+ * Analyzer::query_variants).
+ */
+pub fn snippet(
+    analyzer: &Analyzer,
+    text: &str,
+    lang: Option<&str>,
+    terms: &HashSet<String>,
+) -> String {
+    let matches = find_matches(analyzer, text, lang, terms);
+
+    // the window with the most distinct terms, then the most matches
+    let mut window = 0..floor_boundary(text, SNIPPET_LENGTH);
+    let mut best = (0, 0);
+    for m in &matches {
+        let start = floor_boundary(text, m.range.start.saturating_sub(LEADING_CONTEXT));
+        let end = floor_boundary(text, start + SNIPPET_LENGTH);
+        let inside: Vec<&Match> = matches
+            .iter()
+            .filter(|other| other.range.start >= start && other.range.end <= end)
+            .collect();
+        let distinct: HashSet<&String> = inside.iter().map(|other| &other.term).collect();
+        let score = (distinct.len(), inside.len());
+        if score > best {
+            best = score;
+            window = start..end;
+        }
+    }
+    let window = snap_to_words(text, window);
+
+    let mut snippet = String::new();
+    if window.start > 0 {
+        snippet.push_str("… ");
+    }
+    // bigrams overlap, so join touching matches into one highlight
+    let mut highlights: Vec<Range<usize>> = Vec::new();
+    for m in matches
+        .iter()
+        .filter(|m| m.range.start >= window.start && m.range.end <= window.end)
+    {
+        match highlights.last_mut() {
+            Some(last) if m.range.start <= last.end => last.end = last.end.max(m.range.end),
+            _ => highlights.push(m.range.clone()),
+        }
+    }
+    let mut position = window.start;
+    for highlight in highlights {
+        snippet.push_str(&escape(&text[position..highlight.start]));
+        snippet.push_str(HIGHLIGHT_START);
+        snippet.push_str(&escape(&text[highlight.clone()]));
+        snippet.push_str(HIGHLIGHT_END);
+        position = highlight.end;
+    }
+    snippet.push_str(&escape(&text[position..window.end]));
+    if window.end < text.len() {
+        snippet.push_str(" …");
+    }
+    snippet
+}
+
+/**
+ * Splits the text into words the way the analyzer does (letters and digits, runs of unspaced
+ * scripts as bigrams) and returns the ones whose term is one of the query terms
+ */
+fn find_matches(
+    analyzer: &Analyzer,
+    text: &str,
+    lang: Option<&str>,
+    terms: &HashSet<String>,
+) -> Vec<Match> {
+    let mut matches = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if is_unspaced(c) {
+            let mut run = vec![(start, c)];
+            while let Some(&(i, next)) = chars.peek() {
+                if !is_unspaced(next) {
+                    break;
+                }
+                run.push((i, next));
+                chars.next();
+            }
+            let run_end = start + run.iter().map(|(_, c)| c.len_utf8()).sum::<usize>();
+            let width = run.len().min(2);
+            for (i, window) in run.windows(width).enumerate() {
+                let term: String = window.iter().flat_map(|(_, c)| c.to_lowercase()).collect();
+                if terms.contains(&term) {
+                    let end = run.get(i + width).map_or(run_end, |(end, _)| *end);
+                    matches.push(Match {
+                        range: window[0].0..end,
+                        term,
+                    });
+                }
+            }
+        } else if c.is_alphanumeric() {
+            let mut end = start + c.len_utf8();
+            while let Some(&(i, next)) = chars.peek() {
+                if !(next.is_alphanumeric() || next == '\'' || next == '\u{2019}')
+                    || is_unspaced(next)
+                {
+                    break;
+                }
+                end = i + next.len_utf8();
+                chars.next();
+            }
+            let word = &text[start..end];
+            if let Some(token) = analyzer
+                .terms(word, lang)
+                .into_iter()
+                .find(|token| terms.contains(&token.term))
+            {
+                matches.push(Match {
+                    range: start..end,
+                    term: token.term,
+                });
+            }
+        }
+    }
+    matches
+}
+
+fn floor_boundary(text: &str, mut index: usize) -> usize {
+    if index >= text.len() {
+        return text.len();
+    }
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/**
+ * Moves the start of the window forward and the end back to whitespace so the snippet doesn't
+ * start or end in the middle of a word
+ */
+fn snap_to_words(text: &str, window: Range<usize>) -> Range<usize> {
+    let mut start = window.start;
+    if start > 0 {
+        if let Some(space) = text[start..window.end].find(char::is_whitespace) {
+            start += space + 1;
+        }
+    }
+    let mut end = window.end;
+    if end < text.len() {
+        if let Some(space) = text[start..end].rfind(char::is_whitespace) {
+            end = start + space;
+        }
+    }
+    start..end
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(analyzer: &Analyzer, query: &str) -> HashSet<String> {
+        analyzer
+            .tokens(query)
+            .iter()
+            .flat_map(|token| analyzer.query_variants(token, None))
+            .collect()
+    }
+
+    #[test]
+    fn highlights_matching_words() {
+        let analyzer = Analyzer::new(2, 64);
+        let text = "Solar <power> is Running low & the runner runs.";
+        assert_eq!(
+            snippet(&analyzer, text, Some("eng"), &terms(&analyzer, "run power")),
+            "Solar &lt;<mark>power</mark>&gt; is <mark>Running</mark> low &amp; the runner \
+             <mark>runs</mark>."
+        );
+    }
+
+    #[test]
+    fn picks_the_window_with_the_matches() {
+        let analyzer = Analyzer::new(2, 64);
+        let navigation = "Home About Contact Archive Login ".repeat(20);
+        let text = format!(
+            "{}The interplanetary file system stores content.",
+            navigation
+        );
+        let snippet = snippet(&analyzer, &text, None, &terms(&analyzer, "interplanetary"));
+        assert!(snippet.starts_with("… "));
+        assert!(snippet.contains("<mark>interplanetary</mark> file system stores content."));
+        assert!(snippet.len() < SNIPPET_LENGTH + 30);
+    }
+
+    #[test]
+    fn falls_back_to_the_start_without_matches() {
+        let analyzer = Analyzer::new(2, 64);
+        let text = "word ".repeat(100);
+        let snippet = snippet(&analyzer, &text, None, &terms(&analyzer, "missing"));
+        assert!(snippet.starts_with("word word"));
+        assert!(snippet.ends_with(" …"));
+    }
+
+    #[test]
+    fn highlights_bigrams() {
+        let analyzer = Analyzer::new(2, 64);
+        let text = "星际文件系统是一种分布式文件系统";
+        assert_eq!(
+            snippet(&analyzer, text, None, &terms(&analyzer, "系统")),
+            "星际文件<mark>系统</mark>是一种分布式文件<mark>系统</mark>"
+        );
+        assert_eq!(
+            snippet(&analyzer, text, None, &terms(&analyzer, "文件系统")),
+            "星际<mark>文件系统</mark>是一种分布式<mark>文件系统</mark>"
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use dashmap::DashMap;
+use std::collections::HashMap;
+
+// stem -> the words it was stemmed from, with how often they occur
+pub type Words = HashMap<String, HashMap<String, u32>>;
+
+/**
+ * The words the stemmed terms in the index came from, so a term can be shown to people as the
+ * word it is most often written as ("library" rather than "librari"). Counts only ever grow,
+ * removing a document doesn't change which word is shown for its terms.
+ */
+#[derive(Default)]
+pub struct WordForms {
+    forms: DashMap<String, HashMap<String, u32>>,
+}
+
+impl WordForms {
+    pub fn new() -> Self {
+        WordForms::default()
+    }
+
+    pub fn add(&self, words: &Words) {
+        for (stem, forms) in words {
+            let mut known = self.forms.entry(stem.clone()).or_default();
+            for (word, count) in forms {
+                *known.entry(word.clone()).or_insert(0) += count;
+            }
+        }
+    }
+
+    /**
+     * The word the term is most often written as, ties going to the shortest. Terms that weren't
+     * stemmed are words already.
+     */
+    pub fn word(&self, term: &str) -> String {
+        self.forms
+            .get(term)
+            .and_then(|forms| {
+                forms
+                    .iter()
+                    .max_by(|a, b| {
+                        a.1.cmp(b.1)
+                            .then_with(|| b.0.len().cmp(&a.0.len()))
+                            .then_with(|| b.0.cmp(a.0))
+                    })
+                    .map(|(word, _)| word.clone())
+            })
+            .unwrap_or_else(|| term.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn most_common_word_for_a_stem() {
+        let forms = WordForms::new();
+        let words = |pairs: &[(&str, &str, u32)]| -> Words {
+            let mut words = Words::new();
+            for (stem, word, count) in pairs {
+                words
+                    .entry(stem.to_string())
+                    .or_default()
+                    .insert(word.to_string(), *count);
+            }
+            words
+        };
+        forms.add(&words(&[
+            ("librari", "libraries", 2),
+            ("run", "running", 1),
+        ]));
+        forms.add(&words(&[("librari", "library", 3), ("run", "run", 1)]));
+        assert_eq!(forms.word("librari"), "library");
+        assert_eq!(forms.word("run"), "run");
+        assert_eq!(forms.word("solar"), "solar");
+    }
+}